@@ -75,7 +75,7 @@ fn main() {
     // try it with curl
     // curl 'http://localhost:6767/a/post/request' -H 'Content-Type: application/json;charset=UTF-8'  --data-binary $'{ "firstname": "John","lastname": "Connor" }'
     router.post("/a/post/request", middleware! { |request, response|
-        let person = request.json_as::<Person>().unwrap();
+        let person = try_with!(response, request.json_body::<Person>());
         format!("Hello {} {}", person.firstname, person.lastname)
     });
 
@@ -0,0 +1,22 @@
+#[macro_use] extern crate nickel;
+
+use nickel::{Nickel, Request, Response, MiddlewareResult, HttpRouter};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+
+struct Visits(AtomicUsize);
+
+fn count<'a>(req: &mut Request, res: Response<'a>) -> MiddlewareResult<'a> {
+    let visits = req.server_data::<Visits>().unwrap();
+    res.send(format!("{}", visits.0.fetch_add(1, Relaxed)))
+}
+
+fn main() {
+    let mut server = Nickel::with_data(Visits(AtomicUsize::new(0)));
+
+    server.get("/", count);
+    server.get("/peek", middleware! { |req|
+        format!("{}", req.server_data::<Visits>().unwrap().0.load(Relaxed))
+    });
+    server.listen("127.0.0.1:6767");
+}
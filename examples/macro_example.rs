@@ -104,8 +104,8 @@ fn main() {
 
         // try it with curl
         // curl 'http://localhost:6767/a/post/request' -H 'Content-Type: application/json;charset=UTF-8'  --data-binary $'{ "firstname": "John","lastname": "Connor" }'
-        post "/a/post/request" => |request| {
-            let person = request.json_as::<Person>().unwrap();
+        post "/a/post/request" => |request, response| {
+            let person = try_with!(response, request.json_body::<Person>());
             format!("Hello {} {}", person.firstname, person.lastname)
         }
 
@@ -0,0 +1,187 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use hyper::header::ContentLength;
+use hyper::status::StatusCode;
+use plugin::Extensible;
+use typemap::Key;
+
+use request::{self, Request};
+
+/// The reasons a request body can't be parsed.
+#[derive(Debug)]
+pub enum BodyError {
+    /// Reading the body from the connection failed.
+    Io(io::Error),
+    /// The body is larger than the limit, in bytes, set with
+    /// `ServerConfig::max_body_size`.
+    TooLarge(u64),
+    /// The `Content-Type` of the body doesn't match the parser.
+    UnsupportedMediaType,
+    /// The body can't be parsed, with a message telling where and why.
+    Malformed(String)
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BodyError::Io(ref e) => write!(f, "Failed to read the request body: {}", e),
+            BodyError::TooLarge(limit) => {
+                write!(f, "The request body exceeds the limit of {} bytes", limit)
+            },
+            BodyError::UnsupportedMediaType => f.write_str(self.description()),
+            BodyError::Malformed(ref msg) => f.write_str(msg)
+        }
+    }
+}
+
+impl Error for BodyError {
+    fn description(&self) -> &str {
+        match *self {
+            BodyError::Io(_) => "Failed to read the request body",
+            BodyError::TooLarge(_) => "The request body is too large",
+            BodyError::UnsupportedMediaType => "The request body has an unsupported Content-Type",
+            BodyError::Malformed(ref msg) => msg
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            BodyError::Io(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl BodyError {
+    /// The status of the response telling the client about the error.
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            BodyError::Io(_) => StatusCode::BadRequest,
+            BodyError::TooLarge(_) => StatusCode::PayloadTooLarge,
+            BodyError::UnsupportedMediaType => StatusCode::UnsupportedMediaType,
+            BodyError::Malformed(_) => StatusCode::BadRequest
+        }
+    }
+
+    // Errors are kept along with the body, every caller gets its own copy.
+    fn duplicate(&self) -> BodyError {
+        match *self {
+            BodyError::Io(ref e) => BodyError::Io(io::Error::new(e.kind(), e.to_string())),
+            BodyError::TooLarge(limit) => BodyError::TooLarge(limit),
+            BodyError::UnsupportedMediaType => BodyError::UnsupportedMediaType,
+            BodyError::Malformed(ref msg) => BodyError::Malformed(msg.clone())
+        }
+    }
+}
+
+impl From<BodyError> for (StatusCode, BodyError) {
+    fn from(err: BodyError) -> (StatusCode, BodyError) {
+        (err.status(), err)
+    }
+}
+
+// The outcome of reading the body, kept for the following readers.
+struct RawBodyKey;
+impl Key for RawBodyKey { type Value = Result<Vec<u8>, BodyError>; }
+
+pub trait RawBody {
+    /// The body of the current `Request` as bytes.
+    ///
+    /// The body is read from the connection on the first call and kept, so
+    /// any number of parsers can look at it. Reading fails with
+    /// `413 Payload Too Large` for bodies above
+    /// `ServerConfig::max_body_size`, and later calls fail the same way.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter, RawBody};
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     server.post("/upload", middleware! { |req, res|
+    ///         let body = try_with!(res, req.raw_body());
+    ///         format!("Received {} bytes", body.len())
+    ///     });
+    /// }
+    /// ```
+    fn raw_body(&mut self) -> Result<&[u8], (StatusCode, BodyError)>;
+}
+
+impl<'mw, 'conn> RawBody for Request<'mw, 'conn> {
+    fn raw_body(&mut self) -> Result<&[u8], (StatusCode, BodyError)> {
+        if !self.extensions().contains::<RawBodyKey>() {
+            let body = read(self);
+            self.extensions_mut().insert::<RawBodyKey>(body);
+        }
+
+        match *self.extensions().get::<RawBodyKey>().unwrap() {
+            Ok(ref body) => Ok(body),
+            Err(ref err) => Err(err.duplicate().into())
+        }
+    }
+}
+
+// Replaces the body `RawBody` hands out, e.g. after decompressing it.
+pub fn replace(req: &mut Request, body: Vec<u8>) {
+    req.extensions_mut().insert::<RawBodyKey>(Ok(body));
+}
+
+// The body for parsers which stream it, like `multipart`: the body kept by
+// `RawBody` or swapped in by `RequestDecompressor` if there is one, the
+// connection otherwise.
+pub fn reader<'a, 'mw, 'conn>(req: &'a mut Request<'mw, 'conn>) -> Result<Box<Read + 'a>, BodyError> {
+    if !req.extensions().contains::<RawBodyKey>() {
+        return Ok(Box::new(&mut req.origin))
+    }
+
+    match *req.extensions().get::<RawBodyKey>().unwrap() {
+        Ok(ref body) => Ok(Box::new(&body[..])),
+        Err(ref err) => Err(err.duplicate())
+    }
+}
+
+// Reads the whole body, refusing bodies above `ServerConfig::max_body_size`.
+pub fn read(req: &mut Request) -> Result<Vec<u8>, BodyError> {
+    let limit = request::server(req).max_body_size();
+
+    if let Some(&ContentLength(length)) = req.origin.headers.get::<ContentLength>() {
+        if length > limit {
+            return Err(BodyError::TooLarge(limit))
+        }
+    }
+
+    // Read one byte past the limit to tell a body of exactly `limit` bytes
+    // from a larger one without a Content-Length.
+    let mut body = vec![];
+    try!((&mut req.origin).take(limit.saturating_add(1))
+                          .read_to_end(&mut body)
+                          .map_err(BodyError::Io));
+
+    if body.len() as u64 > limit {
+        return Err(BodyError::TooLarge(limit))
+    }
+
+    Ok(body)
+}
+
+#[test]
+fn keeps_the_body_for_later_readers() {
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use server::ServerConfig;
+    use testing::TestClient;
+
+    let mut server = Nickel::new();
+    server.configure(ServerConfig::new().max_body_size(4));
+    server.post("/", middleware! { |req, res|
+        let first = try_with!(res, req.raw_body()).to_vec();
+        let second = try_with!(res, req.raw_body());
+        format!("{:?} {:?}", first, second)
+    });
+
+    let client = TestClient::new(server);
+    assert_eq!(client.post("/", vec![0xff, 0xfe]).body_str(), Some("[255, 254] [255, 254]"));
+    assert_eq!(client.post("/", "12345").status(), StatusCode::PayloadTooLarge);
+}
@@ -0,0 +1,314 @@
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::str;
+use hyper::header::{ContentLength, ContentType, HttpDate};
+use hyper::method::Method;
+use hyper::server::Request as HyperRequest;
+use hyper::status::StatusCode;
+use rand::{self, Rng};
+
+use {Halt, MiddlewareResult};
+use response::Response;
+
+// Requests for more ranges get the whole file.
+const MAX_RANGES: usize = 32;
+
+/// The `Range` and `If-Range` headers of a `GET` request.
+pub struct RangeRequest {
+    range: String,
+    if_range: Option<String>
+}
+
+/// The parts of a file to send.
+pub enum Ranges {
+    /// The first and last byte of each range, sorted and without overlaps.
+    Satisfiable(Vec<(u64, u64)>),
+    /// None of the requested ranges is inside the file.
+    Unsatisfiable
+}
+
+impl RangeRequest {
+    pub fn from_request(req: &HyperRequest) -> Option<RangeRequest> {
+        if req.method != Method::Get {
+            return None
+        }
+
+        raw_header(req, "Range").map(|range| {
+            RangeRequest {
+                range: range,
+                if_range: raw_header(req, "If-Range")
+            }
+        })
+    }
+
+    /// The ranges of a file of `length` bytes to send, or `None` if all of
+    /// it should be sent.
+    ///
+    /// Ranges are only honoured if `If-Range` is missing or matches the
+    /// date the file was last modified. Invalid `Range` headers are ignored.
+    pub fn resolve(&self, length: u64, last_modified: Option<&HttpDate>) -> Option<Ranges> {
+        if let Some(ref if_range) = self.if_range {
+            // files are sent without an ETag, only dates can match
+            match last_modified {
+                Some(date) if date.to_string() == *if_range => {},
+                _ => return None
+            }
+        }
+
+        parse(&self.range, length)
+    }
+}
+
+fn raw_header(req: &HyperRequest, name: &str) -> Option<String> {
+    req.headers.get_raw(name)
+               .and_then(|lines| lines.first())
+               .and_then(|line| str::from_utf8(line).ok())
+               .map(|line| line.trim().to_string())
+}
+
+fn parse(header: &str, length: u64) -> Option<Ranges> {
+    let mut parts = header.splitn(2, '=');
+    if parts.next().map(|unit| unit.trim()) != Some("bytes") {
+        return None
+    }
+
+    let specs: Vec<&str> = match parts.next() {
+        Some(specs) => specs.split(',').map(|spec| spec.trim()).filter(|spec| !spec.is_empty()).collect(),
+        None => return None
+    };
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return None
+    }
+
+    let mut ranges = vec![];
+    for spec in specs {
+        let mut bounds = spec.splitn(2, '-');
+        let first = bounds.next().unwrap().trim();
+        let last = match bounds.next() {
+            Some(last) => last.trim(),
+            None => return None
+        };
+
+        if first.is_empty() {
+            // `-n` asks for the last `n` bytes
+            let suffix = match last.parse::<u64>() {
+                Ok(suffix) => suffix,
+                Err(_) => return None
+            };
+            if suffix > 0 && length > 0 {
+                ranges.push((length - cmp::min(suffix, length), length - 1));
+            }
+            continue
+        }
+
+        let first = match first.parse::<u64>() {
+            Ok(first) => first,
+            Err(_) => return None
+        };
+        let last = if last.is_empty() {
+            length.saturating_sub(1)
+        } else {
+            match last.parse::<u64>() {
+                Ok(last) if last >= first => cmp::min(last, length.saturating_sub(1)),
+                _ => return None
+            }
+        };
+
+        if first < length {
+            ranges.push((first, last));
+        }
+    }
+
+    if ranges.is_empty() {
+        return Some(Ranges::Unsatisfiable)
+    }
+
+    // overlapping and adjacent ranges are sent as one
+    ranges.sort();
+    let mut merged: Vec<(u64, u64)> = vec![];
+    for (first, last) in ranges {
+        if let Some(previous) = merged.last_mut() {
+            if first <= previous.1 + 1 {
+                previous.1 = cmp::max(previous.1, last);
+                continue
+            }
+        }
+        merged.push((first, last));
+    }
+
+    Some(Ranges::Satisfiable(merged))
+}
+
+/// Sends `ranges` of `file`, which is `length` bytes long, as a
+/// `206 Partial Content` response, or a `416 Range Not Satisfiable` one.
+pub fn send<'a>(mut res: Response<'a>, file: File, length: u64, ranges: Ranges)
+        -> MiddlewareResult<'a> {
+    let ranges = match ranges {
+        Ranges::Satisfiable(ranges) => ranges,
+        Ranges::Unsatisfiable => {
+            res.set(StatusCode::RangeNotSatisfiable);
+            res.headers_mut().set_raw("Content-Range", vec![format!("bytes */{}", length).into_bytes()]);
+            res.headers_mut().set(ContentLength(0));
+            return Ok(Halt(try!(res.start())))
+        }
+    };
+
+    res.set(StatusCode::PartialContent);
+
+    let (parts, tail) = if ranges.len() == 1 {
+        let (first, last) = ranges[0];
+        res.headers_mut().set_raw("Content-Range", vec![content_range(first, last, length).into_bytes()]);
+        (vec![String::new()], String::new())
+    } else {
+        let content_type = res.headers().get::<ContentType>()
+                              .map_or(String::new(), |t| format!("Content-Type: {}\r\n", t));
+        let boundary: String = rand::thread_rng().gen_ascii_chars().take(24).collect();
+        res.headers_mut().set(ContentType(format!("multipart/byteranges; boundary={}", boundary)
+                                              .parse().unwrap()));
+
+        let parts = ranges.iter().enumerate().map(|(i, &(first, last))| {
+            format!("{}--{}\r\n{}Content-Range: {}\r\n\r\n",
+                    if i == 0 { "" } else { "\r\n" },
+                    boundary,
+                    content_type,
+                    content_range(first, last, length))
+        }).collect();
+        (parts, format!("\r\n--{}--\r\n", boundary))
+    };
+
+    let body_length = ranges.iter().zip(parts.iter()).fold(tail.len() as u64, |sum, (&(first, last), part)| {
+        sum + part.len() as u64 + last - first + 1
+    });
+    res.headers_mut().set(ContentLength(body_length));
+
+    let mut stream = try!(res.start());
+    match write_ranges(file, &mut stream, &ranges, &parts, &tail) {
+        Ok(()) => Ok(Halt(stream)),
+        Err(e) => stream.bail(format!("Failed to send file: {}", e))
+    }
+}
+
+fn content_range(first: u64, last: u64, length: u64) -> String {
+    format!("bytes {}-{}/{}", first, last, length)
+}
+
+fn write_ranges<W: Write>(mut file: File,
+                          out: &mut W,
+                          ranges: &[(u64, u64)],
+                          parts: &[String],
+                          tail: &str) -> io::Result<()> {
+    for (&(first, last), part) in ranges.iter().zip(parts.iter()) {
+        try!(out.write_all(part.as_bytes()));
+        try!(file.seek(SeekFrom::Start(first)));
+        try!(io::copy(&mut Read::by_ref(&mut file).take(last - first + 1), out));
+    }
+
+    out.write_all(tail.as_bytes())
+}
+
+#[test]
+fn parses_ranges() {
+    let t = |header: &str| match parse(header, 1000) {
+        Some(Ranges::Satisfiable(ranges)) => Some(Ok(ranges)),
+        Some(Ranges::Unsatisfiable) => Some(Err(())),
+        None => None
+    };
+
+    assert_eq!(t("bytes=0-499"), Some(Ok(vec![(0, 499)])));
+    assert_eq!(t("bytes=500-"), Some(Ok(vec![(500, 999)])));
+    assert_eq!(t("bytes=-100"), Some(Ok(vec![(900, 999)])));
+    assert_eq!(t("bytes=-2000"), Some(Ok(vec![(0, 999)])));
+    assert_eq!(t("bytes=900-5000"), Some(Ok(vec![(900, 999)])));
+    assert_eq!(t("bytes=500-599, 0-99"), Some(Ok(vec![(0, 99), (500, 599)])));
+    assert_eq!(t("bytes=0-99, 50-149, 150-199"), Some(Ok(vec![(0, 199)])));
+
+    assert_eq!(t("bytes=1000-"), Some(Err(())));
+    assert_eq!(t("bytes=-0"), Some(Err(())));
+
+    assert_eq!(t("bytes=5-1"), None);
+    assert_eq!(t("bytes=a-b"), None);
+    assert_eq!(t("items=0-1"), None);
+    assert_eq!(t("bytes="), None);
+}
+
+#[test]
+fn sends_partial_files() {
+    use std::env;
+    use std::fs;
+    use hyper::header::{LastModified, TransferEncoding};
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use testing::{TestClient, TestRequest};
+
+    let path = env::temp_dir().join("nickel-byte-range-test.txt");
+    fs::File::create(&path).unwrap().write_all(b"0123456789abcdefghij").unwrap();
+
+    let mut server = Nickel::new();
+    let file = path.clone();
+    server.get("/file", middleware! { |_, res| return res.send_file(&file) });
+
+    let client = TestClient::new(server);
+    let get = |headers: &[(&str, &str)]| {
+        let mut req = TestRequest::new(Method::Get, "/file");
+        for &(name, value) in headers {
+            req = req.raw_header(name, value);
+        }
+        client.dispatch(req)
+    };
+
+    let res = get(&[]);
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(res.headers().get_raw("Accept-Ranges"), Some(&[b"bytes".to_vec()][..]));
+    assert_eq!(res.body_str(), Some("0123456789abcdefghij"));
+
+    let res = get(&[("Range", "bytes=5-9")]);
+    assert_eq!(res.status(), StatusCode::PartialContent);
+    assert_eq!(res.headers().get_raw("Content-Range"), Some(&[b"bytes 5-9/20".to_vec()][..]));
+    assert_eq!(res.body_str(), Some("56789"));
+
+    let res = get(&[("Range", "bytes=0-1,-2")]);
+    assert_eq!(res.status(), StatusCode::PartialContent);
+    assert!(!res.headers().has::<TransferEncoding>());
+    let body = res.body_str().unwrap();
+    assert!(body.contains("Content-Range: bytes 0-1/20\r\n\r\n01\r\n"));
+    assert!(body.contains("Content-Range: bytes 18-19/20\r\n\r\nij\r\n"));
+
+    assert_eq!(get(&[("Range", "bytes=20-")]).status(), StatusCode::RangeNotSatisfiable);
+
+    let last_modified = get(&[]).headers().get::<LastModified>().unwrap().to_string();
+    let res = get(&[("Range", "bytes=5-9"), ("If-Range", &last_modified[..])]);
+    assert_eq!(res.status(), StatusCode::PartialContent);
+    // the file changed since, send all of it
+    let res = get(&[("Range", "bytes=5-9"), ("If-Range", "Thu, 01 Jan 1970 00:00:00 GMT")]);
+    assert_eq!(res.status(), StatusCode::Ok);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn ignores_ranges_for_other_statuses() {
+    use std::env;
+    use std::fs;
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use testing::{TestClient, TestRequest};
+
+    let path = env::temp_dir().join("nickel-byte-range-404-test.html");
+    fs::File::create(&path).unwrap().write_all(b"<h1>Not Found</h1>").unwrap();
+
+    let mut server = Nickel::new();
+    let file = path.clone();
+    server.utilize(middleware! { |_, mut res|
+        res.set(StatusCode::NotFound);
+        return res.send_file(&file)
+    });
+
+    let client = TestClient::new(server);
+    let res = client.dispatch(TestRequest::new(Method::Get, "/missing").raw_header("Range", "bytes=0-3"));
+    assert_eq!(res.status(), StatusCode::NotFound);
+    assert!(res.headers().get_raw("Accept-Ranges").is_none());
+    assert_eq!(res.body_str(), Some("<h1>Not Found</h1>"));
+
+    fs::remove_file(&path).unwrap();
+}
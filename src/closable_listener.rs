@@ -0,0 +1,168 @@
+use std::cmp;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hyper;
+use hyper::net::NetworkListener;
+
+/// Wraps the listener a server accepts connections on, so the socket can be
+/// closed while hyper's acceptor threads are blocked on it.
+///
+/// hyper clones the listener for each acceptor thread and never stops them,
+/// so only `Unbind` owns the socket. Acceptors borrow a copy for each
+/// `accept` and, once the socket is closed, give it back and park for good.
+pub struct ClosableListener<L> {
+    shared: Arc<Shared<L>>
+}
+
+struct Shared<L> {
+    listener: Mutex<Option<L>>,
+    // Connecting here wakes acceptors blocked in `accept`. Only TCP
+    // sockets have an address to connect to.
+    wake: Option<SocketAddr>,
+    // Number of acceptors holding a copy of the socket.
+    accepting: Mutex<usize>,
+    released: Condvar
+}
+
+/// Closes the socket of a `ClosableListener`.
+pub trait Unbind: Send + Sync {
+    /// Closes the socket and waits up to `timeout` for the acceptors to
+    /// release their copies of it. Returns `false` if some were still
+    /// blocked when the timeout expired.
+    fn unbind(&self, timeout: Duration) -> bool;
+}
+
+impl<L: NetworkListener + Send + 'static> ClosableListener<L> {
+    pub fn new(mut listener: L) -> ClosableListener<L> {
+        let wake = listener.local_addr().ok().and_then(wake_addr);
+
+        ClosableListener {
+            shared: Arc::new(Shared {
+                listener: Mutex::new(Some(listener)),
+                wake: wake,
+                accepting: Mutex::new(0),
+                released: Condvar::new()
+            })
+        }
+    }
+
+    pub fn unbinder(&self) -> Box<Unbind> {
+        Box::new(self.clone())
+    }
+}
+
+impl<L> Clone for ClosableListener<L> {
+    fn clone(&self) -> ClosableListener<L> {
+        ClosableListener { shared: self.shared.clone() }
+    }
+}
+
+impl<L: NetworkListener + Send + 'static> NetworkListener for ClosableListener<L> {
+    type Stream = L::Stream;
+
+    fn accept(&mut self) -> hyper::Result<L::Stream> {
+        let listener = {
+            let listener = self.shared.listener.lock().unwrap();
+            listener.as_ref().map(|listener| {
+                *self.shared.accepting.lock().unwrap() += 1;
+                listener.clone()
+            })
+        };
+        let mut listener = match listener {
+            Some(listener) => listener,
+            None => park_forever()
+        };
+
+        let result = listener.accept();
+        drop(listener);
+
+        let closed = self.shared.listener.lock().unwrap().is_none();
+        *self.shared.accepting.lock().unwrap() -= 1;
+        self.shared.released.notify_all();
+
+        if closed {
+            // connections arriving while closing are dropped, this also
+            // ends the ones used to wake the acceptors
+            drop(result);
+            park_forever()
+        }
+
+        result
+    }
+
+    fn local_addr(&mut self) -> io::Result<SocketAddr> {
+        match *self.shared.listener.lock().unwrap() {
+            Some(ref mut listener) => listener.local_addr(),
+            None => Err(io::Error::new(io::ErrorKind::NotConnected, "listener is closed"))
+        }
+    }
+}
+
+impl<L: NetworkListener + Send + 'static> Unbind for ClosableListener<L> {
+    fn unbind(&self, timeout: Duration) -> bool {
+        drop(self.shared.listener.lock().unwrap().take());
+
+        let deadline = Instant::now() + timeout;
+        let mut accepting = self.shared.accepting.lock().unwrap();
+        while *accepting > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                warn!("Closing the listener timed out with {} blocked acceptors", *accepting);
+                return false
+            }
+
+            if let Some(addr) = self.shared.wake {
+                let _ = TcpStream::connect(addr);
+            }
+            let wait = cmp::min(deadline - now, Duration::from_millis(10));
+            accepting = self.shared.released.wait_timeout(accepting, wait).unwrap().0;
+        }
+
+        true
+    }
+}
+
+// The address to connect to for waking acceptors blocked on a socket
+// bound to `addr`.
+fn wake_addr(addr: SocketAddr) -> Option<SocketAddr> {
+    if addr.port() == 0 {
+        return None
+    }
+
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1)),
+        ip => ip
+    };
+    Some(SocketAddr::new(ip, addr.port()))
+}
+
+fn park_forever() -> ! {
+    loop {
+        thread::park();
+    }
+}
+
+#[test]
+fn closes_the_socket_while_accepting() {
+    use std::net::TcpListener;
+    use tcp_listener::TcpSocketListener;
+
+    let socket = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    let listener = ClosableListener::new(TcpSocketListener::from(socket));
+
+    for _ in 0..2 {
+        let mut acceptor = listener.clone();
+        thread::spawn(move || { let _ = acceptor.accept(); });
+    }
+    // let the acceptors block in `accept`
+    thread::sleep(Duration::from_millis(50));
+
+    assert!(listener.unbinder().unbind(Duration::from_secs(1)));
+    assert!(TcpStream::connect(addr).is_err());
+}
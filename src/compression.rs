@@ -0,0 +1,311 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use hyper::header::{AcceptEncoding, ContentEncoding, ContentLength, ContentType, Encoding};
+use hyper::mime::Mime;
+use hyper::net::Fresh;
+use hyper::status::{StatusCode, StatusClass};
+
+use middleware::{Middleware, MiddlewareResult, Continue};
+use mimes::MediaType;
+use request::Request;
+use response::{self, Response};
+
+/// Compresses response bodies with gzip or deflate, whichever the client
+/// prefers according to its `Accept-Encoding` header.
+///
+/// Only bodies of the listed media types are compressed, and only if they
+/// are at least `min_size` bytes long. Bodies of unknown length, like
+/// rendered templates, are always compressed. Responses which already have
+/// a `Content-Encoding` are left alone, and so are the partial responses to
+/// `Range` requests, whose byte offsets refer to the uncompressed body.
+/// Compressed responses tell clients not to send `Range` requests for them
+/// with `Accept-Ranges: none`.
+///
+/// # Examples
+/// ```{rust}
+/// use nickel::{Nickel, ResponseCompressor, MediaType};
+///
+/// let mut server = Nickel::new();
+/// server.utilize(ResponseCompressor::new()
+///                    .min_size(512)
+///                    .media_types(&[MediaType::Html, MediaType::Json]));
+/// ```
+pub struct ResponseCompressor {
+    min_size: u64,
+    media_types: Vec<MediaType>
+}
+
+impl ResponseCompressor {
+    /// Creates a middleware compressing HTML, CSS, JavaScript, JSON, XML,
+    /// SVG, CSV and plain text bodies of at least one kilobyte.
+    pub fn new() -> ResponseCompressor {
+        ResponseCompressor {
+            min_size: 1024,
+            media_types: vec![MediaType::Html, MediaType::Css, MediaType::Js, MediaType::Json,
+                              MediaType::Xml, MediaType::Svg, MediaType::Csv, MediaType::Txt]
+        }
+    }
+
+    /// The smallest body, in bytes, worth compressing.
+    pub fn min_size(mut self, min_size: u64) -> ResponseCompressor {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Replaces the media types which get compressed.
+    pub fn media_types(mut self, media_types: &[MediaType]) -> ResponseCompressor {
+        self.media_types = media_types.to_vec();
+        self
+    }
+
+    fn compress(&self, res: &mut Response<Fresh>, encoding: Option<&Encoding>) {
+        let status = res.status();
+        if status.class() == StatusClass::Informational || status == StatusCode::NoContent
+                || status == StatusCode::NotModified || status == StatusCode::PartialContent
+                || status == StatusCode::RangeNotSatisfiable || res.headers().has::<ContentEncoding>() {
+            return
+        }
+
+        let compressible = match res.headers().get::<ContentType>() {
+            Some(&ContentType(Mime(ref top, ref sub, _))) => self.media_types.iter().any(|&t| {
+                let Mime(t_top, t_sub, _) = t.into();
+                *top == t_top && *sub == t_sub
+            }),
+            None => false
+        };
+        if !compressible {
+            return
+        }
+
+        // caches must tell clients which can't decompress from the others,
+        // even when this response isn't compressed
+        res.add_vary("Accept-Encoding");
+
+        let encoding = match encoding {
+            Some(encoding) => encoding,
+            None => return
+        };
+        if let Some(&ContentLength(length)) = res.headers().get::<ContentLength>() {
+            if length < self.min_size {
+                return
+            }
+        }
+
+        // ranges of the compressed body can't be served
+        if res.headers().get_raw("Accept-Ranges").is_some() {
+            res.headers_mut().set_raw("Accept-Ranges", vec![b"none".to_vec()]);
+        }
+        res.headers_mut().remove::<ContentLength>();
+        res.headers_mut().set(ContentEncoding(vec![encoding.clone()]));
+        response::set_encoder(res, Encoder::new(encoding));
+    }
+}
+
+impl Middleware for ResponseCompressor {
+    fn invoke<'mw, 'conn>(&'mw self, req: &mut Request<'mw, 'conn>, mut res: Response<'mw>)
+                          -> MiddlewareResult<'mw> {
+        let encoding = negotiate(req.origin.headers.get::<AcceptEncoding>());
+        res.on_send(move |res| self.compress(res, encoding.as_ref()));
+        Ok(Continue(res))
+    }
+}
+
+// Picks gzip or deflate, whichever has the higher quality, preferring gzip.
+fn negotiate(accept: Option<&AcceptEncoding>) -> Option<Encoding> {
+    let accepted = match accept {
+        Some(&AcceptEncoding(ref accepted)) => accepted,
+        None => return None
+    };
+
+    let quality = |encoding: &Encoding| {
+        let any = Encoding::EncodingExt("*".to_string());
+        accepted.iter()
+                .find(|item| item.item == *encoding)
+                .or_else(|| accepted.iter().find(|item| item.item == any))
+                .map_or(0, |item| item.quality.0)
+    };
+
+    let (gzip, deflate) = (quality(&Encoding::Gzip), quality(&Encoding::Deflate));
+    if gzip > 0 && gzip >= deflate {
+        Some(Encoding::Gzip)
+    } else if deflate > 0 {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+/// Compresses the body of a streaming `Response` on its way to the client.
+pub struct Encoder {
+    inner: Inner,
+    output: Output
+}
+
+enum Inner {
+    Gzip(GzEncoder<Output>),
+    Deflate(ZlibEncoder<Output>)
+}
+
+// Collects the compressed bytes until they're passed on to the connection.
+#[derive(Clone)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend(buf.iter().cloned());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Encoder {
+    /// # Panics
+    ///
+    /// Panics for encodings other than gzip and deflate.
+    pub fn new(encoding: &Encoding) -> Encoder {
+        let output = Output(Rc::new(RefCell::new(vec![])));
+        let inner = match *encoding {
+            Encoding::Gzip => Inner::Gzip(GzEncoder::new(output.clone(), Compression::Default)),
+            Encoding::Deflate => Inner::Deflate(ZlibEncoder::new(output.clone(), Compression::Default)),
+            ref encoding => panic!("Can't compress responses with {}", encoding)
+        };
+
+        Encoder { inner: inner, output: output }
+    }
+
+    pub fn write(&mut self, buf: &[u8], out: &mut Write) -> io::Result<usize> {
+        let written = try!(match self.inner {
+            Inner::Gzip(ref mut encoder) => encoder.write(buf),
+            Inner::Deflate(ref mut encoder) => encoder.write(buf)
+        });
+        try!(Encoder::drain(&self.output, out));
+        Ok(written)
+    }
+
+    pub fn flush(&mut self, out: &mut Write) -> io::Result<()> {
+        try!(match self.inner {
+            Inner::Gzip(ref mut encoder) => encoder.flush(),
+            Inner::Deflate(ref mut encoder) => encoder.flush()
+        });
+        Encoder::drain(&self.output, out)
+    }
+
+    /// Writes the end of the compressed stream.
+    pub fn finish(self, out: &mut Write) -> io::Result<()> {
+        try!(match self.inner {
+            Inner::Gzip(encoder) => encoder.finish().map(|_| ()),
+            Inner::Deflate(encoder) => encoder.finish().map(|_| ())
+        });
+        Encoder::drain(&self.output, out)
+    }
+
+    fn drain(output: &Output, out: &mut Write) -> io::Result<()> {
+        let mut buf = output.0.borrow_mut();
+        if !buf.is_empty() {
+            try!(out.write_all(&buf));
+            buf.clear();
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn compresses_responses() {
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+    use hyper::method::Method;
+    use hyper::header::{qitem, Vary};
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use testing::{TestClient, TestRequest};
+
+    let mut server = Nickel::new();
+    server.utilize(ResponseCompressor::new().min_size(16));
+    server.get("/large", middleware!("hello world, hello world, hello world"));
+    server.get("/small", middleware!("hello"));
+    server.get("/image", middleware! { |_, mut res|
+        res.set(MediaType::Png);
+        "not really a png, but long enough"
+    });
+
+    let client = TestClient::new(server);
+    let get = |path: &str, encodings: Vec<Encoding>| {
+        let accept = AcceptEncoding(encodings.into_iter().map(qitem).collect());
+        client.dispatch(TestRequest::new(Method::Get, path).header(accept))
+    };
+
+    let res = get("/large", vec![Encoding::Gzip, Encoding::Deflate]);
+    assert_eq!(res.headers().get::<ContentEncoding>(), Some(&ContentEncoding(vec![Encoding::Gzip])));
+    assert!(res.headers().get::<Vary>().is_some());
+    let mut body = String::new();
+    GzDecoder::new(res.body()).unwrap().read_to_string(&mut body).unwrap();
+    assert_eq!(body, "hello world, hello world, hello world");
+
+    let res = get("/large", vec![Encoding::Deflate]);
+    assert_eq!(res.headers().get::<ContentEncoding>(), Some(&ContentEncoding(vec![Encoding::Deflate])));
+
+    let res = get("/large", vec![]);
+    assert!(!res.headers().has::<ContentEncoding>());
+    assert!(res.headers().has::<Vary>());
+    assert_eq!(res.body_str(), Some("hello world, hello world, hello world"));
+
+    let res = get("/small", vec![Encoding::Gzip]);
+    assert!(!res.headers().has::<ContentEncoding>());
+    assert_eq!(res.body_str(), Some("hello"));
+
+    let res = get("/image", vec![Encoding::Gzip]);
+    assert!(!res.headers().has::<ContentEncoding>());
+    assert!(!res.headers().has::<Vary>());
+}
+
+#[test]
+fn compresses_whole_files_only() {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+    use hyper::header::qitem;
+    use hyper::method::Method;
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use testing::{TestClient, TestRequest};
+
+    let path = env::temp_dir().join("nickel-compression-range-test.txt");
+    File::create(&path).unwrap().write_all(b"hello world, hello world, hello world").unwrap();
+
+    let mut server = Nickel::new();
+    server.utilize(ResponseCompressor::new().min_size(0));
+    let file = path.clone();
+    server.get("/file", middleware! { |_, res| return res.send_file(&file) });
+
+    let client = TestClient::new(server);
+    let get = |range: Option<&str>| {
+        let mut req = TestRequest::new(Method::Get, "/file")
+                          .header(AcceptEncoding(vec![qitem(Encoding::Gzip)]));
+        if let Some(range) = range {
+            req = req.raw_header("Range", range);
+        }
+        client.dispatch(req)
+    };
+
+    let res = get(None);
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(res.headers().get::<ContentEncoding>(), Some(&ContentEncoding(vec![Encoding::Gzip])));
+    assert_eq!(res.headers().get_raw("Accept-Ranges"), Some(&[b"none".to_vec()][..]));
+    let mut body = String::new();
+    GzDecoder::new(res.body()).unwrap().read_to_string(&mut body).unwrap();
+    assert_eq!(body, "hello world, hello world, hello world");
+
+    let res = get(Some("bytes=6-10"));
+    assert_eq!(res.status(), StatusCode::PartialContent);
+    assert!(!res.headers().has::<ContentEncoding>());
+    assert_eq!(res.body_str(), Some("world"));
+
+    fs::remove_file(&path).unwrap();
+}
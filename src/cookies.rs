@@ -0,0 +1,268 @@
+use std::fmt;
+use std::slice;
+use std::time::Duration;
+use cookie::{Cookie, CookieJar};
+use hyper::header::Cookie as CookieHeader;
+use plugin::{Plugin, Pluggable};
+use rand::{OsRng, Rng};
+use request::{self, Request};
+use time::Tm;
+use typemap::Key;
+
+/// The cookies sent with a request.
+pub struct RequestCookies {
+    cookies: Vec<Cookie>,
+    key: Vec<u8>
+}
+
+impl RequestCookies {
+    /// Retrieves the value of the cookie called `name`, or `None` if the
+    /// client didn't send it.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies.iter()
+                    .find(|cookie| cookie.name == name)
+                    .map(|cookie| &*cookie.value)
+    }
+
+    /// Retrieves the value of a cookie set with `Response::set_signed_cookie`,
+    /// or `None` if it's missing or its signature doesn't match.
+    pub fn get_signed(&self, name: &str) -> Option<String> {
+        self.jar().signed().find(name).map(|cookie| cookie.value)
+    }
+
+    /// Retrieves the value of a cookie set with
+    /// `Response::set_encrypted_cookie`, or `None` if it's missing or can't
+    /// be decrypted.
+    pub fn get_encrypted(&self, name: &str) -> Option<String> {
+        self.jar().encrypted().find(name).map(|cookie| cookie.value)
+    }
+
+    /// Iterates over all cookies, as sent by the client.
+    pub fn iter(&self) -> slice::Iter<Cookie> {
+        self.cookies.iter()
+    }
+
+    fn jar(&self) -> CookieJar<'static> {
+        let mut jar = CookieJar::new(&self.key);
+        for cookie in &self.cookies {
+            jar.add_original(cookie.clone());
+        }
+        jar
+    }
+}
+
+// Plugin boilerplate
+struct CookieParser;
+impl Key for CookieParser { type Value = RequestCookies; }
+
+impl<'mw, 'conn> Plugin<Request<'mw, 'conn>> for CookieParser {
+    type Error = ();
+
+    fn eval(req: &mut Request) -> Result<RequestCookies, ()> {
+        let cookies = req.origin.headers.get::<CookieHeader>()
+                                        .map(|header| header.0.clone())
+                                        .unwrap_or(vec![]);
+
+        Ok(RequestCookies {
+            cookies: cookies,
+            key: request::server(req).secret_key().to_vec()
+        })
+    }
+}
+
+pub trait Cookies {
+    /// Retrieve the cookies sent with the current `Request`.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter, Cookies};
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     server.get("/", middleware! { |req|
+    ///         match req.cookies().get("theme") {
+    ///             Some(theme) => format!("Using the {} theme", theme),
+    ///             None => "Using the default theme".to_string()
+    ///         }
+    ///     });
+    /// }
+    /// ```
+    fn cookies(&mut self) -> &RequestCookies;
+}
+
+impl<'mw, 'conn> Cookies for Request<'mw, 'conn> {
+    fn cookies(&mut self) -> &RequestCookies {
+        self.get_ref::<CookieParser>()
+            .ok()
+            .expect("Bug: CookieParser returned None")
+    }
+}
+
+/// Restricts when a cookie is sent along with requests made from other sites.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Browsers only accept this along with `Secure`.
+    None
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None"
+        })
+    }
+}
+
+/// Builds a cookie to be passed to `Response::set_cookie`.
+#[derive(Clone, Debug)]
+pub struct CookieBuilder(Cookie);
+
+impl CookieBuilder {
+    /// Starts a cookie without any attributes, which the client deletes
+    /// when the browser is closed.
+    pub fn new<N: Into<String>, V: Into<String>>(name: N, value: V) -> CookieBuilder {
+        CookieBuilder(Cookie::new(name.into(), value.into()))
+    }
+
+    /// Limits the cookie to requests below `path`.
+    pub fn path<P: Into<String>>(mut self, path: P) -> CookieBuilder {
+        self.0.path = Some(path.into());
+        self
+    }
+
+    /// Sends the cookie to `domain` and its subdomains.
+    pub fn domain<D: Into<String>>(mut self, domain: D) -> CookieBuilder {
+        self.0.domain = Some(domain.into());
+        self
+    }
+
+    /// Keeps the cookie for `max_age`, with a precision of seconds.
+    pub fn max_age(mut self, max_age: Duration) -> CookieBuilder {
+        self.0.max_age = Some(max_age.as_secs());
+        self
+    }
+
+    /// Keeps the cookie until `expires`. `max_age` takes precedence in
+    /// clients that support it.
+    pub fn expires(mut self, expires: Tm) -> CookieBuilder {
+        self.0.expires = Some(expires);
+        self
+    }
+
+    /// Only sends the cookie over TLS connections.
+    pub fn secure(mut self, secure: bool) -> CookieBuilder {
+        self.0.secure = secure;
+        self
+    }
+
+    /// Hides the cookie from scripts running in the browser.
+    pub fn http_only(mut self, http_only: bool) -> CookieBuilder {
+        self.0.httponly = http_only;
+        self
+    }
+
+    /// Controls whether the cookie is sent with requests from other sites.
+    pub fn same_site(mut self, same_site: SameSite) -> CookieBuilder {
+        self.0.custom.insert("SameSite".to_string(), same_site.to_string());
+        self
+    }
+
+    /// Returns the built cookie.
+    pub fn finish(self) -> Cookie {
+        self.0
+    }
+}
+
+impl From<CookieBuilder> for Cookie {
+    fn from(builder: CookieBuilder) -> Cookie {
+        builder.finish()
+    }
+}
+
+// Used when the application doesn't set a secret key.
+pub fn random_key() -> Vec<u8> {
+    let mut rng = OsRng::new().ok().expect("Failed to access the OS random number generator");
+    rng.gen_iter::<u8>().take(64).collect()
+}
+
+#[test]
+fn builds_cookies_with_attributes() {
+    let cookie = CookieBuilder::new("theme", "dark")
+                     .path("/")
+                     .domain("nickel.rs")
+                     .max_age(Duration::from_secs(3600))
+                     .secure(true)
+                     .http_only(true)
+                     .same_site(SameSite::Strict)
+                     .finish();
+
+    let header = cookie.to_string();
+    assert!(header.starts_with("theme=dark"));
+    for attribute in &["Path=/", "Domain=nickel.rs", "Max-Age=3600",
+                       "Secure", "HttpOnly", "SameSite=Strict"] {
+        assert!(header.contains(*attribute), "{} is missing {}", header, attribute);
+    }
+}
+
+#[test]
+fn reads_plain_and_signed_cookies() {
+    use hyper::header::SetCookie;
+    use hyper::method::Method;
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use testing::{TestClient, TestRequest};
+
+    let mut server = Nickel::new();
+    server.secret_key("test key");
+    server.get("/set", middleware! { |_, mut res|
+        res.set_cookie(CookieBuilder::new("plain", "hello"));
+        res.set_signed_cookie(CookieBuilder::new("signed", "admin"));
+        ""
+    });
+    server.get("/get", middleware! { |req|
+        let cookies = req.cookies();
+        format!("{:?} {:?}", cookies.get("plain"), cookies.get_signed("signed"))
+    });
+
+    let client = TestClient::new(server);
+    let cookies = client.get("/set").headers().get::<SetCookie>().unwrap().0.clone();
+    assert_eq!(cookies.len(), 2);
+    assert!(cookies[1].value != "admin");
+
+    let get = |cookies: Vec<Cookie>| {
+        let req = TestRequest::new(Method::Get, "/get").header(CookieHeader(cookies));
+        client.dispatch(req).body_str().unwrap().to_string()
+    };
+    assert_eq!(get(cookies.clone()), r#"Some("hello") Some("admin")"#);
+
+    // a changed value no longer matches its signature
+    let mut tampered = cookies.clone();
+    tampered[1].value = tampered[1].value.replace("admin", "super");
+    assert_eq!(get(tampered), r#"Some("hello") None"#);
+}
+
+#[test]
+fn removes_cookies() {
+    use hyper::header::SetCookie;
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use testing::TestClient;
+
+    let mut server = Nickel::new();
+    server.get("/", middleware! { |_, mut res|
+        res.remove_cookie("theme");
+        ""
+    });
+
+    let res = TestClient::new(server).get("/");
+    let cookies = res.headers().get::<SetCookie>().unwrap();
+    assert_eq!(cookies[0].name, "theme");
+    assert_eq!(cookies[0].value, "");
+    assert_eq!(cookies[0].max_age, Some(0));
+    assert_eq!(cookies[0].path, Some("/".to_string()));
+}
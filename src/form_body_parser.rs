@@ -0,0 +1,89 @@
+use hyper::header::ContentType;
+use hyper::mime::{Mime, TopLevel, SubLevel};
+use hyper::status::StatusCode;
+use plugin::{Plugin, Pluggable};
+use typemap::Key;
+
+use body::{BodyError, RawBody};
+use query_string::{self, Query};
+use request::Request;
+
+// Plugin boilerplate
+struct FormBodyParser;
+impl Key for FormBodyParser { type Value = Query; }
+
+impl<'mw, 'conn> Plugin<Request<'mw, 'conn>> for FormBodyParser {
+    type Error = (StatusCode, BodyError);
+
+    fn eval(req: &mut Request) -> Result<Query, (StatusCode, BodyError)> {
+        match req.origin.headers.get::<ContentType>() {
+            Some(&ContentType(Mime(TopLevel::Application, SubLevel::WwwFormUrlEncoded, _))) => {},
+            _ => return Err(BodyError::UnsupportedMediaType.into())
+        }
+
+        let body = try!(req.raw_body());
+        Ok(query_string::from_form_body(body))
+    }
+}
+
+pub trait FormBody {
+    /// Parses the `application/x-www-form-urlencoded` body of the current
+    /// `Request`, as sent by HTML forms.
+    ///
+    /// Fails with `415 Unsupported Media Type` for other types of bodies
+    /// and with `413 Payload Too Large` for bodies above
+    /// `ServerConfig::max_body_size`.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter, FormBody};
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     server.post("/signup", middleware! { |req, res|
+    ///         let form = try_with!(res, req.form_body());
+    ///         format!("Welcome {}", form.get("name").unwrap_or("stranger"))
+    ///     });
+    /// }
+    /// ```
+    fn form_body(&mut self) -> Result<&Query, (StatusCode, BodyError)>;
+}
+
+impl<'mw, 'conn> FormBody for Request<'mw, 'conn> {
+    fn form_body(&mut self) -> Result<&Query, (StatusCode, BodyError)> {
+        self.get_ref::<FormBodyParser>()
+    }
+}
+
+#[test]
+fn parses_form_bodies() {
+    use hyper::method::Method;
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use server::ServerConfig;
+    use testing::{TestClient, TestRequest};
+
+    let mut server = Nickel::new();
+    server.configure(ServerConfig::new().max_body_size(32));
+    server.post("/", middleware! { |req, res|
+        let form = try_with!(res, req.form_body());
+        format!("{:?} {:?}", form.get("name"), form.all("tag"))
+    });
+
+    let client = TestClient::new(server);
+    let form = |body: &str| {
+        TestRequest::new(Method::Post, "/")
+            .header(ContentType("application/x-www-form-urlencoded".parse().unwrap()))
+            .body(body)
+    };
+
+    let res = client.dispatch(form("name=John+Doe&tag=a&tag=b%26c"));
+    assert_eq!(res.body_str(), Some(r#"Some("John Doe") Some(["a", "b&c"])"#));
+
+    let res = client.dispatch(form("name=a-name-which-is-too-long-for-the-limit"));
+    assert_eq!(res.status(), StatusCode::PayloadTooLarge);
+
+    let res = client.post("/", "name=John");
+    assert_eq!(res.status(), StatusCode::UnsupportedMediaType);
+}
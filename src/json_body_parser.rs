@@ -1,33 +1,144 @@
 use serialize::{Decodable, json};
+use serialize::json::{DecoderError, ParserError};
 use request::Request;
-use typemap::Key;
-use plugin::{Plugin, Pluggable};
+use body::{BodyError, RawBody};
+use hyper::header::ContentType;
+use hyper::mime::{Mime, TopLevel, SubLevel};
+use hyper::status::StatusCode;
 use std::io;
-use std::io::{Read, ErrorKind};
-
-// Plugin boilerplate
-struct JsonBodyParser;
-impl Key for JsonBodyParser { type Value = String; }
-impl<'mw, 'conn> Plugin<Request<'mw, 'conn>> for JsonBodyParser {
-    type Error = io::Error;
-
-    fn eval(req: &mut Request) -> Result<String, io::Error> {
-        let mut s = String::new();
-        try!(req.origin.read_to_string(&mut s));
-        Ok(s)
-    }
-}
+use std::io::ErrorKind;
+use std::str;
 
 pub trait JsonBody {
+    /// Decodes the body of the current `Request` as JSON, regardless of its
+    /// `Content-Type`. All failures are reported as an `io::Error`, prefer
+    /// `json_body` which tells them apart.
     fn json_as<T: Decodable>(&mut self) -> Result<T, io::Error>;
+
+    /// Decodes the `application/json` body of the current `Request` into
+    /// a `T`.
+    ///
+    /// Fails with `415 Unsupported Media Type` for other types of bodies,
+    /// with `413 Payload Too Large` for bodies above
+    /// `ServerConfig::max_body_size` and with `400 Bad Request` for bodies
+    /// which aren't valid JSON or don't match `T`. The message tells the
+    /// line and column of syntax errors, or the field which doesn't match.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// extern crate rustc_serialize;
+    /// use nickel::{Nickel, HttpRouter, JsonBody};
+    ///
+    /// #[derive(RustcDecodable)]
+    /// struct Person {
+    ///     name: String
+    /// }
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     server.post("/people", middleware! { |req, res|
+    ///         let person = try_with!(res, req.json_body::<Person>());
+    ///         format!("Hello {}", person.name)
+    ///     });
+    /// }
+    /// ```
+    fn json_body<T: Decodable>(&mut self) -> Result<T, (StatusCode, BodyError)>;
 }
 
 impl<'mw, 'conn> JsonBody for Request<'mw, 'conn> {
     fn json_as<T: Decodable>(&mut self) -> Result<T, io::Error> {
-        self.get_ref::<JsonBodyParser>().and_then(|parsed|
-            json::decode::<T>(&*parsed).map_err(|err|
-                io::Error::new(ErrorKind::Other, format!("Parse error: {}", err))
-            )
+        let body = try!(self.raw_body().map_err(|(_, err)| io::Error::new(ErrorKind::Other, err)));
+        let body = try!(str::from_utf8(body).map_err(|err| io::Error::new(ErrorKind::InvalidData, err)));
+        json::decode::<T>(body).map_err(|err|
+            io::Error::new(ErrorKind::Other, format!("Parse error: {}", err))
         )
     }
+
+    fn json_body<T: Decodable>(&mut self) -> Result<T, (StatusCode, BodyError)> {
+        let is_json = match self.origin.headers.get::<ContentType>() {
+            Some(&ContentType(Mime(TopLevel::Application, SubLevel::Json, _))) => true,
+            // e.g. application/vnd.api+json
+            Some(&ContentType(Mime(TopLevel::Application, SubLevel::Ext(ref sub), _))) => {
+                sub.ends_with("+json")
+            },
+            _ => false
+        };
+        if !is_json {
+            return Err(BodyError::UnsupportedMediaType.into())
+        }
+
+        let body = try!(self.raw_body());
+        let body = try!(str::from_utf8(body).map_err(|err| {
+            BodyError::Malformed(format!("Invalid JSON: not UTF-8 after byte {}", err.valid_up_to()))
+        }));
+
+        json::decode(body).map_err(|err| malformed(err).into())
+    }
+}
+
+fn malformed(err: DecoderError) -> BodyError {
+    let msg = match err {
+        DecoderError::ParseError(ParserError::SyntaxError(code, line, column)) => {
+            format!("Invalid JSON at line {} column {}: {}", line, column, json::error_str(code))
+        },
+        DecoderError::ParseError(ParserError::IoError(e)) => return BodyError::Io(e),
+        DecoderError::ExpectedError(expected, found) => {
+            format!("Invalid JSON: expected {} but found {}", expected, found)
+        },
+        DecoderError::MissingFieldError(field) => format!("Invalid JSON: missing field '{}'", field),
+        DecoderError::UnknownVariantError(variant) => {
+            format!("Invalid JSON: unknown variant '{}'", variant)
+        },
+        DecoderError::ApplicationError(msg) => format!("Invalid JSON: {}", msg),
+        DecoderError::EOF => "Invalid JSON: unexpected end of input".to_string()
+    };
+
+    BodyError::Malformed(msg)
+}
+
+#[test]
+fn reports_json_errors() {
+    use std::error::Error;
+    use hyper::method::Method;
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use server::ServerConfig;
+    use testing::{TestClient, TestRequest};
+
+    struct Person { name: String }
+
+    impl Decodable for Person {
+        fn decode<D: ::serialize::Decoder>(d: &mut D) -> Result<Person, D::Error> {
+            d.read_struct("Person", 1, |d| Ok(Person {
+                name: try!(d.read_struct_field("name", 0, Decodable::decode))
+            }))
+        }
+    }
+
+    let mut server = Nickel::new();
+    server.configure(ServerConfig::new().max_body_size(32));
+    server.post("/", middleware! { |req|
+        match req.json_body::<Person>() {
+            Ok(person) => format!("Hello {}", person.name),
+            Err((status, err)) => format!("{} {}", status.to_u16(), err.description())
+        }
+    });
+
+    let client = TestClient::new(server);
+    let post = |content_type: &str, body: &str| {
+        let req = TestRequest::new(Method::Post, "/")
+                      .header(ContentType(content_type.parse().unwrap()))
+                      .body(body);
+        client.dispatch(req).body_str().unwrap().to_string()
+    };
+
+    assert_eq!(post("application/json", r#"{"name": "John"}"#), "Hello John");
+    assert_eq!(post("application/merge-patch+json", r#"{"name": "Jane"}"#), "Hello Jane");
+    assert!(post("text/plain", r#"{"name": "John"}"#).starts_with("415 "));
+    assert!(post("application/json", r#"{"name": "a name too long for the limit"}"#)
+                .starts_with("413 "));
+    assert!(post("application/json", "{\n  \"name\": }").starts_with("400 Invalid JSON at line 2 "));
+    assert_eq!(post("application/json", r#"{"nom": "John"}"#),
+               "400 Invalid JSON: missing field 'name'");
 }
@@ -10,27 +10,49 @@ extern crate url;
 extern crate mustache;
 extern crate groupable;
 extern crate modifier;
+extern crate cookie;
+extern crate rand;
+extern crate flate2;
+#[cfg(unix)] extern crate unix_socket;
+#[cfg(unix)] extern crate libc;
 
 #[macro_use] extern crate log;
 #[macro_use] extern crate lazy_static;
 
 pub use nickel::Nickel;
+pub use server::{ListeningServer, ServerConfig};
+pub use proxy::TrustedProxy;
 pub use request::Request;
 pub use response::Response;
 pub use middleware::{Action, Continue, Halt, Middleware, ErrorHandler, MiddlewareResult};
 pub use static_files_handler::StaticFilesHandler;
+pub use request_decompressor::RequestDecompressor;
+pub use compression::ResponseCompressor;
+pub use mount::Mount;
 pub use favicon_handler::FaviconHandler;
 pub use default_error_handler::DefaultErrorHandler;
 pub use json_body_parser::JsonBody;
+pub use form_body_parser::FormBody;
+pub use body::{BodyError, RawBody};
+pub use multipart::MultipartBody;
 pub use query_string::{QueryString, Query};
-pub use router::{Router, Route, RouteResult, HttpRouter};
+pub use cookies::{Cookies, RequestCookies, CookieBuilder, SameSite};
+pub use cookie::Cookie;
+pub use session::{Sessions, SessionMiddleware};
+pub use router::{Router, Route, RouteGroup, RouteResult, HttpRouter};
 pub use nickel_error::NickelError;
+pub use param_error::ParamError;
 pub use mimes::MediaType;
 pub use responder::Responder;
+pub use tcp_listener::TcpSocketListener;
+#[cfg(unix)] pub use unix_listener::UnixSocketListener;
 
 #[macro_use] pub mod macros;
 
 pub mod router;
+pub mod testing;
+pub mod session;
+pub mod multipart;
 mod server;
 mod nickel;
 mod request;
@@ -39,12 +61,25 @@ mod middleware;
 mod responder;
 mod favicon_handler;
 mod static_files_handler;
+mod request_decompressor;
+mod compression;
+mod byte_range;
+mod mount;
 mod json_body_parser;
+mod form_body_parser;
+mod body;
 pub mod mimes;
 mod query_string;
+mod query_decoder;
+mod cookies;
 mod urlencoded;
 mod nickel_error;
+mod param_error;
+mod proxy;
 mod default_error_handler;
+mod tcp_listener;
+mod closable_listener;
+#[cfg(unix)] mod unix_listener;
 
 pub mod status {
     pub use hyper::status::StatusCode;
@@ -12,3 +12,48 @@ macro_rules! try_with {
         }
     }};
 }
+
+/// Sends a different response depending on the media types the client
+/// accepts, see `Request::accepts`.
+///
+/// Sets the `Content-Type` of the response to the chosen media type and adds
+/// `Accept` to the `Vary` header. Only the chosen response is evaluated.
+/// When the client accepts none of the media types, the result is a `406 Not
+/// Acceptable` error.
+///
+/// # Examples
+/// ```{rust}
+/// #[macro_use] extern crate nickel;
+/// use nickel::{Nickel, HttpRouter, MediaType, Request, Response, MiddlewareResult};
+///
+/// fn greet<'a>(req: &mut Request, res: Response<'a>) -> MiddlewareResult<'a> {
+///     respond_to!(req, res, {
+///         MediaType::Json => r#"{"greeting": "hello"}"#,
+///         MediaType::Html => "<h1>hello</h1>",
+///         MediaType::Txt => "hello"
+///     })
+/// }
+///
+/// fn main() {
+///     let mut server = Nickel::new();
+///     server.get("/", greet);
+/// }
+/// ```
+#[macro_export]
+macro_rules! respond_to {
+    ($req:expr, $res:expr, { $($media_type:expr => $body:expr),+ $(,)* }) => {{
+        let mut res = $res;
+        res.add_vary("Accept");
+
+        match $req.accepts(&[$($media_type),+]) {
+            $(
+                ::std::option::Option::Some(media_type) if media_type == $media_type => {
+                    res.set(media_type);
+                    res.send($body)
+                }
+            )+
+            _ => res.error($crate::status::StatusCode::NotAcceptable,
+                           "None of the available media types is acceptable")
+        }
+    }};
+}
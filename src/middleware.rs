@@ -1,7 +1,13 @@
-use request::Request;
-use response::Response;
+use request::{self, Request};
+use response::{self, Response};
 use nickel_error::NickelError;
+use hyper::header::Headers;
 use hyper::net;
+use hyper::status::StatusCode;
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
 
 pub use self::Action::{Continue, Halt};
 
@@ -53,8 +59,40 @@ impl MiddlewareStack {
     }
 
     pub fn invoke<'mw, 'conn>(&'mw self, mut req: Request<'mw, 'conn>, mut res: Response<'mw>) {
+        // Receives the response if a handler panics before starting it
+        let rescue = Rc::new(RefCell::new(None));
+        response::set_rescue(&mut res, rescue.clone());
+
         for handler in self.handlers.iter() {
-            match handler.invoke(&mut req, res) {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| handler.invoke(&mut req, res)));
+
+            let result = match result {
+                Ok(result) => result,
+                Err(cause) => {
+                    let message = format!("Handler panicked: {}", panic_message(&cause));
+                    error!("{} {:?} {:?} {:?}",
+                           message,
+                           req.origin.method,
+                           req.origin.remote_addr,
+                           req.origin.uri);
+
+                    let rescued = rescue.borrow_mut().take();
+                    match rescued {
+                        Some(mut origin) => {
+                            // Drop whatever the handler had set so far,
+                            // like a `Content-Length` for a body it never sent
+                            *origin.headers_mut() = Headers::new();
+                            let res = Response::from_internal(origin, request::server(&req));
+                            Err(NickelError::new(res, message, StatusCode::InternalServerError))
+                        },
+                        // The response had been started, the client already
+                        // got a status and part of the body
+                        None => Err(unsafe { NickelError::without_response(message) })
+                    }
+                }
+            };
+
+            match result {
                 Ok(Halt(res)) => {
                     debug!("Halted {:?} {:?} {:?} {:?}",
                            req.origin.method,
@@ -85,7 +123,11 @@ impl MiddlewareStack {
                           req.origin.remote_addr,
                           req.origin.uri,
                           err.message,
-                          err.stream.map(|s| s.status()));
+                          err.stream.as_ref().map(|s| s.status()));
+
+                    // Finishes a compressed body, which would be cut short
+                    // if the stream was only dropped
+                    let _ = err.end();
                     return
                 }
             }
@@ -99,3 +141,49 @@ impl MiddlewareStack {
         }
     }
 }
+
+fn panic_message(cause: &Box<Any + Send>) -> &str {
+    match cause.downcast_ref::<&'static str>() {
+        Some(s) => s,
+        None => match cause.downcast_ref::<String>() {
+            Some(s) => s,
+            None => "Box<Any>"
+        }
+    }
+}
+
+#[test]
+fn turns_panics_into_internal_server_errors() {
+    use std::io::Write;
+    use hyper::header::ContentLength;
+    use {Nickel, HttpRouter};
+    use testing::TestClient;
+
+    fn handle_error(err: &mut NickelError, _: &mut Request) -> Action {
+        if let Some(ref mut res) = err.stream {
+            let status = res.status().to_u16();
+            let _ = write!(res, "{}: {}", status, err.message);
+        }
+        Halt(())
+    }
+
+    let mut server = Nickel::new();
+    server.get("/panic", middleware! { |_, mut res|
+        res.set(ContentLength(100));
+        if true { panic!("oh no") }
+        "unreachable"
+    });
+    server.get("/fine", middleware!("fine"));
+    let handler: fn(&mut NickelError, &mut Request) -> Action = handle_error;
+    server.handle_error(handler);
+
+    let client = TestClient::new(server);
+
+    let res = client.get("/panic");
+    assert_eq!(res.status(), StatusCode::InternalServerError);
+    assert_eq!(res.body_str(), Some("500: Handler panicked: oh no"));
+
+    let res = client.get("/fine");
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(res.body_str(), Some("fine"));
+}
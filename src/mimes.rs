@@ -2,7 +2,7 @@ use hyper::mime::Mime;
 use std::str::FromStr;
 
 macro_rules! mimes {
-    ($($t:expr { $($name:ident, $as_s:pat, $subt:expr,)+ })+) => (
+    ($($t:expr { $($name:ident, $as_s:tt, $subt:expr,)+ })+) => (
         #[allow(non_camel_case_types)]
         #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
         pub enum MediaType {
@@ -41,6 +41,10 @@ macro_rules! mimes {
                 }
             }
         }
+
+        /// The file extensions of all media types, as accepted by
+        /// `MediaType::from_str`.
+        pub static EXTENSIONS: &'static [&'static str] = &[$($($as_s,)*)*];
     )
 }
 
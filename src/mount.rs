@@ -0,0 +1,140 @@
+use std::mem;
+use hyper::uri::RequestUri::AbsolutePath;
+use plugin::Extensible;
+use typemap::Key;
+
+use request::Request;
+use response::Response;
+use middleware::{Middleware, MiddlewareResult, Continue};
+
+/// Hands requests whose path starts with a prefix to another middleware,
+/// with the prefix stripped from the path.
+///
+/// This lets a `Router` or `StaticFilesHandler` be reused unchanged under
+/// different prefixes. The middleware can get the prefix it is mounted at
+/// through `Request::mount_point`.
+///
+/// # Examples
+/// ```{rust}
+/// #[macro_use] extern crate nickel;
+/// use nickel::{Nickel, HttpRouter, Mount};
+///
+/// fn main() {
+///     let mut server = Nickel::new();
+///     let mut api = Nickel::router();
+///
+///     // handles /api/v1/users
+///     api.get("/users", middleware! { |req|
+///         format!("Users, mounted at {}", req.mount_point())
+///     });
+///
+///     server.utilize(Mount::new("/api/v1", api));
+/// }
+/// ```
+pub struct Mount<M> {
+    mount_point: String,
+    middleware: M
+}
+
+impl<M: Middleware> Mount<M> {
+    /// Creates a new `Mount`. Leading and trailing slashes in `mount_point`
+    /// are optional, `"/api/"`, `"api"` and `"/api"` are all equivalent.
+    pub fn new<S: AsRef<str>>(mount_point: S, middleware: M) -> Mount<M> {
+        let trimmed = mount_point.as_ref().trim_matches('/');
+
+        Mount {
+            mount_point: if trimmed.is_empty() {
+                String::new()
+            } else {
+                format!("/{}", trimmed)
+            },
+            middleware: middleware
+        }
+    }
+}
+
+impl<M: Middleware> Middleware for Mount<M> {
+    fn invoke<'mw, 'conn>(&'mw self, req: &mut Request<'mw, 'conn>, res: Response<'mw>)
+                          -> MiddlewareResult<'mw> {
+        let subpath = match req.origin.uri {
+            AbsolutePath(ref path) => match strip_mount_point(path, &self.mount_point) {
+                Some(subpath) => subpath,
+                None => return Ok(Continue(res))
+            },
+            _ => return Ok(Continue(res))
+        };
+
+        debug!("Mount::invoke for '{}' at '{}'", subpath, self.mount_point);
+
+        let mount_point = format!("{}{}", req.mount_point(), self.mount_point);
+        let original_uri = mem::replace(&mut req.origin.uri, AbsolutePath(subpath));
+        let original_mount_point = req.extensions_mut().insert::<MountPoint>(mount_point);
+
+        let result = self.middleware.invoke(req, res);
+
+        req.origin.uri = original_uri;
+        match original_mount_point {
+            Some(mount_point) => { req.extensions_mut().insert::<MountPoint>(mount_point); },
+            None => { req.extensions_mut().remove::<MountPoint>(); }
+        }
+
+        result
+    }
+}
+
+// The prefix all active `Mount`s have stripped from the request path.
+pub struct MountPoint;
+impl Key for MountPoint { type Value = String; }
+
+fn strip_mount_point(path: &str, mount_point: &str) -> Option<String> {
+    if !path.starts_with(mount_point) {
+        return None
+    }
+
+    let rest = &path[mount_point.len()..];
+    if rest.is_empty() || rest.starts_with('?') {
+        Some(format!("/{}", rest))
+    } else if rest.starts_with('/') {
+        Some(rest.to_string())
+    } else {
+        // e.g. '/apiary' for the mount point '/api'
+        None
+    }
+}
+
+#[test]
+fn strips_mount_points() {
+    assert_eq!(strip_mount_point("/api/users", "/api"), Some("/users".to_string()));
+    assert_eq!(strip_mount_point("/api", "/api"), Some("/".to_string()));
+    assert_eq!(strip_mount_point("/api?q=1", "/api"), Some("/?q=1".to_string()));
+    assert_eq!(strip_mount_point("/api/?q=1", "/api"), Some("/?q=1".to_string()));
+    assert_eq!(strip_mount_point("/apiary", "/api"), None);
+    assert_eq!(strip_mount_point("/other/api", "/api"), None);
+    assert_eq!(strip_mount_point("/users", ""), Some("/users".to_string()));
+}
+
+#[test]
+fn mounts_routers_under_a_prefix() {
+    use {Nickel, HttpRouter};
+    use hyper::status::StatusCode;
+    use testing::TestClient;
+
+    let mut server = Nickel::new();
+    let mut users = Nickel::router();
+    users.get("/users/:id", middleware! { |req|
+        format!("{} {}", req.mount_point(), req.param("id").unwrap())
+    });
+
+    let mut v2 = Nickel::router();
+    v2.get("/", middleware! { |req| format!("root of {}", req.mount_point()) });
+
+    server.mount("/api/v1/", users);
+    server.mount("/api", Mount::new("v2", v2));
+
+    let client = TestClient::new(server);
+
+    assert_eq!(client.get("/api/v1/users/42").body_str(), Some("/api/v1 42"));
+    assert_eq!(client.get("/api/v2").body_str(), Some("root of /api/v2"));
+    assert_eq!(client.get("/users/42").status(), StatusCode::NotFound);
+    assert_eq!(client.get("/api/v1x/users/42").status(), StatusCode::NotFound);
+}
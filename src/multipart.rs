@@ -0,0 +1,781 @@
+//! Streaming parser for `multipart/form-data` request bodies, as sent by
+//! HTML forms with file uploads.
+//!
+//! # Examples
+//! ```{rust}
+//! #[macro_use] extern crate nickel;
+//! use nickel::{Nickel, HttpRouter, MultipartBody};
+//!
+//! fn main() {
+//!     let mut server = Nickel::new();
+//!     server.post("/upload", middleware! { |req, res|
+//!         let mut multipart = try_with!(res, req.multipart());
+//!         let mut uploaded = vec![];
+//!
+//!         while let Some(mut part) = try_with!(res, multipart.next_part()) {
+//!             if part.is_file() {
+//!                 let file = try_with!(res, part.save_temp());
+//!                 uploaded.push(format!("{} ({} bytes)", part.filename().unwrap(), file.size()));
+//!                 // `file.persist(...)` keeps the file, otherwise it is
+//!                 // deleted when `file` goes out of scope.
+//!             }
+//!         }
+//!
+//!         format!("Uploaded {}", uploaded.join(", "))
+//!     });
+//! }
+//! ```
+use std::cmp;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use hyper::header::{ContentType, Headers};
+use hyper::mime::{Attr, Mime, TopLevel, SubLevel, Value};
+use hyper::status::StatusCode;
+use rand::{self, Rng};
+
+use body::{self, BodyError};
+use request::{self, Request};
+
+// Parts and the whole body are read in chunks of this size.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+// The largest block of headers accepted for a single part.
+const MAX_HEADERS_SIZE: usize = 8 * 1024;
+
+/// The reasons a `multipart/form-data` body can't be parsed.
+#[derive(Debug)]
+pub enum MultipartError {
+    /// Reading the body from the connection failed.
+    Io(io::Error),
+    /// Writing a part to its destination failed.
+    Storage(io::Error),
+    /// The request isn't `multipart/form-data` with a boundary.
+    UnsupportedMediaType,
+    /// The body doesn't follow the `multipart/form-data` format.
+    Malformed(&'static str),
+    /// A part is larger than the limit, in bytes.
+    PartTooLarge(u64),
+    /// The body is larger than the limit, in bytes.
+    TooLarge(u64)
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MultipartError::Io(ref e) => write!(f, "Failed to read the request body: {}", e),
+            MultipartError::Storage(ref e) => write!(f, "Failed to store an uploaded part: {}", e),
+            MultipartError::Malformed(reason) => write!(f, "Invalid multipart body: {}", reason),
+            MultipartError::PartTooLarge(limit) => {
+                write!(f, "A part of the request body exceeds the limit of {} bytes", limit)
+            },
+            MultipartError::TooLarge(limit) => {
+                write!(f, "The request body exceeds the limit of {} bytes", limit)
+            },
+            MultipartError::UnsupportedMediaType => f.write_str(self.description())
+        }
+    }
+}
+
+impl Error for MultipartError {
+    fn description(&self) -> &str {
+        match *self {
+            MultipartError::Io(_) => "Failed to read the request body",
+            MultipartError::Storage(_) => "Failed to store an uploaded part",
+            MultipartError::UnsupportedMediaType => "Expected a multipart/form-data body",
+            MultipartError::Malformed(_) => "Invalid multipart body",
+            MultipartError::PartTooLarge(_) => "A part of the request body is too large",
+            MultipartError::TooLarge(_) => "The request body is too large"
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            MultipartError::Io(ref e) | MultipartError::Storage(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl MultipartError {
+    /// The status of the response telling the client about the error.
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            MultipartError::Io(_) | MultipartError::Malformed(_) => StatusCode::BadRequest,
+            MultipartError::Storage(_) => StatusCode::InternalServerError,
+            MultipartError::UnsupportedMediaType => StatusCode::UnsupportedMediaType,
+            MultipartError::PartTooLarge(_) |
+            MultipartError::TooLarge(_) => StatusCode::PayloadTooLarge
+        }
+    }
+}
+
+impl From<MultipartError> for (StatusCode, MultipartError) {
+    fn from(err: MultipartError) -> (StatusCode, MultipartError) {
+        (err.status(), err)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    // Before the first delimiter.
+    Preamble,
+    // Inside the body of a part.
+    Body,
+    // Right after a delimiter, before the headers of the next part.
+    Delimiter,
+    // After the closing delimiter.
+    Done
+}
+
+/// Reads the parts of a `multipart/form-data` body one after the other.
+///
+/// By default a part may be up to 16 megabytes and the whole body up to
+/// 64 megabytes, larger bodies fail with `413 Payload Too Large`.
+pub struct Multipart<R> {
+    source: R,
+    // Bytes read from `source` which haven't been consumed yet.
+    buf: Vec<u8>,
+    // `\r\n--` followed by the boundary.
+    delimiter: Vec<u8>,
+    state: State,
+    eof: bool,
+    total_size: u64,
+    part_size: u64,
+    max_total_size: u64,
+    max_part_size: u64,
+    max_field_size: u64
+}
+
+impl<R: Read> Multipart<R> {
+    /// Parses the body read from `source`, with parts separated by
+    /// `boundary`.
+    pub fn new(source: R, boundary: &str) -> Multipart<R> {
+        Multipart {
+            source: source,
+            // Lets the first delimiter, which may start the body, be found
+            // just like the ones which follow a part.
+            buf: b"\r\n".to_vec(),
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            state: State::Preamble,
+            eof: false,
+            total_size: 0,
+            part_size: 0,
+            max_total_size: 64 * 1024 * 1024,
+            max_part_size: 16 * 1024 * 1024,
+            max_field_size: 1024 * 1024
+        }
+    }
+
+    /// The largest body, in bytes, including the headers of all parts.
+    pub fn max_total_size(mut self, max: u64) -> Multipart<R> {
+        self.max_total_size = max;
+        self
+    }
+
+    /// The largest part, in bytes, excluding its headers.
+    pub fn max_part_size(mut self, max: u64) -> Multipart<R> {
+        self.max_part_size = max;
+        self
+    }
+
+    /// The largest part, in bytes, read into memory by `Part::text`.
+    pub fn max_field_size(mut self, max: u64) -> Multipart<R> {
+        self.max_field_size = max;
+        self
+    }
+
+    /// Moves to the next part, skipping whatever is left of the current
+    /// one. Returns `None` after the last part.
+    pub fn next_part(&mut self) -> Result<Option<Part<R>>, (StatusCode, MultipartError)> {
+        match self.advance() {
+            Ok(Some((headers, name, filename))) => Ok(Some(Part {
+                multipart: self,
+                headers: headers,
+                name: name,
+                filename: filename
+            })),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    fn advance(&mut self) -> Result<Option<(Headers, String, Option<String>)>, MultipartError> {
+        match self.state {
+            State::Preamble => try!(self.skip_preamble()),
+            State::Body => {
+                let mut scratch = [0; CHUNK_SIZE];
+                while try!(self.read_body(&mut scratch)) > 0 {}
+            },
+            State::Delimiter => {},
+            State::Done => return Ok(None)
+        }
+
+        // The closing delimiter is followed by `--`, the others by
+        // optional whitespace and a line break.
+        try!(self.fill_to(2));
+        if self.buf.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok(None)
+        }
+
+        let line_end = try!(self.find_within(b"\r\n", 1024, "Missing line break after boundary"));
+        if !self.buf[..line_end].iter().all(|&b| b == b' ' || b == b'\t') {
+            return Err(MultipartError::Malformed("Unexpected data after boundary"))
+        }
+        self.consume(line_end + 2);
+
+        try!(self.fill_to(2));
+        let headers = if self.buf.starts_with(b"\r\n") {
+            self.consume(2);
+            Headers::new()
+        } else {
+            let end = try!(self.find_within(b"\r\n\r\n", MAX_HEADERS_SIZE,
+                                            "Part headers are too large"));
+            let headers = try!(parse_headers(&self.buf[..end]));
+            self.consume(end + 4);
+            headers
+        };
+
+        let (name, filename) = match headers.get_raw("Content-Disposition")
+                                            .and_then(|values| values.first())
+                                            .and_then(|value| str::from_utf8(value).ok())
+                                            .and_then(parse_content_disposition) {
+            Some(disposition) => disposition,
+            None => return Err(MultipartError::Malformed("Part without a form-data name"))
+        };
+
+        self.state = State::Body;
+        self.part_size = 0;
+        Ok(Some((headers, name, filename)))
+    }
+
+    fn skip_preamble(&mut self) -> Result<(), MultipartError> {
+        loop {
+            if let Some(pos) = find(&self.buf, &self.delimiter) {
+                let len = pos + self.delimiter.len();
+                self.consume(len);
+                self.state = State::Delimiter;
+                return Ok(())
+            }
+
+            // Keep what could be the start of the delimiter.
+            let keep = cmp::min(self.buf.len(), self.delimiter.len() - 1);
+            let len = self.buf.len() - keep;
+            self.consume(len);
+
+            if try!(self.fill()) == 0 {
+                return Err(MultipartError::Malformed("Missing boundary"))
+            }
+        }
+    }
+
+    // Reads from the body of the current part, returns 0 at its end.
+    fn read_body(&mut self, out: &mut [u8]) -> Result<usize, MultipartError> {
+        if self.state != State::Body || out.is_empty() {
+            return Ok(0)
+        }
+
+        loop {
+            let available = match find(&self.buf, &self.delimiter) {
+                Some(0) => {
+                    let len = self.delimiter.len();
+                    self.consume(len);
+                    self.state = State::Delimiter;
+                    return Ok(0)
+                },
+                Some(pos) => pos,
+                // Keep what could be the start of the delimiter.
+                None => self.buf.len().saturating_sub(self.delimiter.len() - 1)
+            };
+
+            if available > 0 {
+                let len = cmp::min(available, out.len());
+                out[..len].copy_from_slice(&self.buf[..len]);
+                self.consume(len);
+
+                self.part_size += len as u64;
+                if self.part_size > self.max_part_size {
+                    return Err(MultipartError::PartTooLarge(self.max_part_size))
+                }
+                return Ok(len)
+            }
+
+            if try!(self.fill()) == 0 {
+                return Err(MultipartError::Malformed("Missing closing boundary"))
+            }
+        }
+    }
+
+    // Reads the next chunk of the body into the buffer.
+    fn fill(&mut self) -> Result<usize, MultipartError> {
+        if self.eof {
+            return Ok(0)
+        }
+
+        let mut chunk = [0; CHUNK_SIZE];
+        let len = loop {
+            match self.source.read(&mut chunk) {
+                Ok(len) => break len,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => return Err(MultipartError::Io(e))
+            }
+        };
+
+        self.eof = len == 0;
+        self.total_size += len as u64;
+        if self.total_size > self.max_total_size {
+            return Err(MultipartError::TooLarge(self.max_total_size))
+        }
+
+        self.buf.extend_from_slice(&chunk[..len]);
+        Ok(len)
+    }
+
+    // Reads until the buffer holds at least `len` bytes, returns `false`
+    // if the body ended before.
+    fn fill_to(&mut self, len: usize) -> Result<bool, MultipartError> {
+        while self.buf.len() < len {
+            if try!(self.fill()) == 0 {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+
+    // Reads until `needle` shows up within the first `limit` bytes of
+    // the buffer and returns its position.
+    fn find_within(&mut self, needle: &[u8], limit: usize, error: &'static str)
+            -> Result<usize, MultipartError> {
+        loop {
+            if let Some(pos) = find(&self.buf, needle) {
+                if pos <= limit {
+                    return Ok(pos)
+                }
+            }
+
+            if self.buf.len() > limit + needle.len() || try!(self.fill()) == 0 {
+                return Err(MultipartError::Malformed(error))
+            }
+        }
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.buf.drain(..len);
+    }
+}
+
+/// A text field or file in a `multipart/form-data` body.
+///
+/// The content is read from the connection while it's consumed, through
+/// `Read`, `text`, `copy_to` or `save_temp`.
+pub struct Part<'a, R: 'a> {
+    multipart: &'a mut Multipart<R>,
+    headers: Headers,
+    name: String,
+    filename: Option<String>
+}
+
+impl<'a, R: Read> Part<'a, R> {
+    /// The name of the form field.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name of the uploaded file, as given by the client, or `None` for
+    /// text fields.
+    ///
+    /// Never use it as a path without checking it first, it may contain
+    /// things like `../`.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_ref().map(|filename| &**filename)
+    }
+
+    /// Whether the part is an uploaded file.
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    /// The media type of the part, if the client sent one.
+    pub fn content_type(&self) -> Option<&Mime> {
+        self.headers.get::<ContentType>().map(|content_type| &content_type.0)
+    }
+
+    /// All headers of the part.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Reads the whole part into a string.
+    ///
+    /// Fails with `413 Payload Too Large` for parts larger than
+    /// `Multipart::max_field_size`.
+    pub fn text(&mut self) -> Result<String, (StatusCode, MultipartError)> {
+        let limit = self.multipart.max_field_size;
+        let mut text = vec![];
+        let mut chunk = [0; CHUNK_SIZE];
+
+        loop {
+            let len = try!(self.multipart.read_body(&mut chunk));
+            if len == 0 {
+                break
+            }
+
+            text.extend_from_slice(&chunk[..len]);
+            if text.len() as u64 > limit {
+                return Err(MultipartError::PartTooLarge(limit).into())
+            }
+        }
+
+        String::from_utf8(text).map_err(|_| {
+            MultipartError::Malformed("Text field is not valid UTF-8").into()
+        })
+    }
+
+    /// Streams the rest of the part into `writer` and returns the number
+    /// of bytes written.
+    pub fn copy_to<W: Write>(&mut self, writer: &mut W)
+            -> Result<u64, (StatusCode, MultipartError)> {
+        let mut written = 0;
+        let mut chunk = [0; CHUNK_SIZE];
+
+        loop {
+            let len = try!(self.multipart.read_body(&mut chunk));
+            if len == 0 {
+                return Ok(written)
+            }
+
+            try!(writer.write_all(&chunk[..len]).map_err(MultipartError::Storage));
+            written += len as u64;
+        }
+    }
+
+    /// Streams the rest of the part into a new file in the temporary
+    /// directory. The file is deleted when the `TempFile` is dropped,
+    /// unless it's kept with `TempFile::persist`.
+    pub fn save_temp(&mut self) -> Result<TempFile, (StatusCode, MultipartError)> {
+        let (mut file, mut temp) = try!(TempFile::create().map_err(MultipartError::Storage));
+        temp.size = try!(self.copy_to(&mut file));
+        try!(file.sync_all().map_err(MultipartError::Storage));
+        Ok(temp)
+    }
+}
+
+impl<'a, R: Read> Read for Part<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.multipart.read_body(buf).map_err(|err| match err {
+            MultipartError::Io(e) => e,
+            err => io::Error::new(ErrorKind::InvalidData, err)
+        })
+    }
+}
+
+/// An uploaded file stored by `Part::save_temp`.
+pub struct TempFile {
+    path: PathBuf,
+    size: u64,
+    persisted: bool
+}
+
+impl TempFile {
+    fn create() -> io::Result<(File, TempFile)> {
+        loop {
+            let name: String = rand::thread_rng().gen_ascii_chars().take(16).collect();
+            let path = env::temp_dir().join(format!("nickel-upload-{}", name));
+
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((file, TempFile { path: path, size: 0, persisted: false })),
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e)
+            }
+        }
+    }
+
+    /// Where the file is stored.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The size of the file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Moves the file to `path` instead of deleting it.
+    pub fn persist<P: AsRef<Path>>(mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        match fs::rename(&self.path, path) {
+            Ok(()) => self.persisted = true,
+            // Renaming fails across file systems.
+            Err(_) => { try!(fs::copy(&self.path, path)); }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("Failed to remove temporary file {:?}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+pub trait MultipartBody {
+    /// Starts parsing the `multipart/form-data` body of the current
+    /// `Request`.
+    ///
+    /// Fails with `415 Unsupported Media Type` for other types of bodies.
+    /// Text fields read with `Part::text` are limited to
+    /// `ServerConfig::max_body_size`.
+    ///
+    /// The body is streamed from the connection, unless it was already read
+    /// with `RawBody` or inflated by `RequestDecompressor`.
+    fn multipart<'a>(&'a mut self) -> Result<Multipart<Box<Read + 'a>>, (StatusCode, MultipartError)>;
+}
+
+impl<'mw, 'conn> MultipartBody for Request<'mw, 'conn> {
+    fn multipart<'a>(&'a mut self) -> Result<Multipart<Box<Read + 'a>>, (StatusCode, MultipartError)> {
+        let boundary = match self.origin.headers.get::<ContentType>() {
+            Some(&ContentType(Mime(TopLevel::Multipart, SubLevel::FormData, ref params))) => {
+                params.iter()
+                      .find(|&&(ref attr, _)| *attr == Attr::Boundary)
+                      .and_then(|&(_, ref value)| match *value {
+                          Value::Ext(ref boundary) => Some(boundary.trim_matches('"').to_string()),
+                          _ => None
+                      })
+            },
+            _ => None
+        };
+
+        let boundary = match boundary {
+            Some(ref boundary) if !boundary.is_empty() => boundary.clone(),
+            _ => return Err(MultipartError::UnsupportedMediaType.into())
+        };
+
+        let max_field_size = request::server(self).max_body_size();
+        let source = try!(body::reader(self).map_err(from_body_error));
+        Ok(Multipart::new(source, &boundary).max_field_size(max_field_size))
+    }
+}
+
+fn from_body_error(err: BodyError) -> (StatusCode, MultipartError) {
+    let err = match err {
+        BodyError::Io(e) => MultipartError::Io(e),
+        BodyError::TooLarge(limit) => MultipartError::TooLarge(limit),
+        BodyError::UnsupportedMediaType => MultipartError::UnsupportedMediaType,
+        BodyError::Malformed(_) => MultipartError::Malformed("The request body is corrupt")
+    };
+    err.into()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn parse_headers(raw: &[u8]) -> Result<Headers, MultipartError> {
+    let raw = try!(str::from_utf8(raw).map_err(|_| {
+        MultipartError::Malformed("Part headers are not valid UTF-8")
+    }));
+
+    let mut headers = Headers::new();
+    for line in raw.split("\r\n") {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap().trim();
+        let value = match parts.next() {
+            Some(value) if !name.is_empty() => value.trim(),
+            _ => return Err(MultipartError::Malformed("Invalid part header"))
+        };
+        headers.set_raw(name.to_string(), vec![value.as_bytes().to_vec()]);
+    }
+
+    Ok(headers)
+}
+
+// Extracts the `name` and `filename` of a `form-data` disposition.
+fn parse_content_disposition(value: &str) -> Option<(String, Option<String>)> {
+    let mut params = split_params(value).into_iter();
+    match params.next() {
+        Some((ref kind, None)) if kind.eq_ignore_ascii_case("form-data") => {},
+        _ => return None
+    }
+
+    let mut name = None;
+    let mut filename = None;
+    for (key, value) in params {
+        if key.eq_ignore_ascii_case("name") {
+            name = value;
+        } else if key.eq_ignore_ascii_case("filename") {
+            filename = value;
+        }
+    }
+
+    name.map(|name| (name, filename))
+}
+
+// Splits `a; b=c; d="e; f"` into its parameters, unquoting the values.
+fn split_params(value: &str) -> Vec<(String, Option<String>)> {
+    let mut params = vec![];
+    let mut chars = value.chars().peekable();
+
+    loop {
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == ';' || c == '=' {
+                break
+            }
+            key.push(c);
+            chars.next();
+        }
+
+        let value = if chars.peek() == Some(&'=') {
+            chars.next();
+            while chars.peek() == Some(&' ') {
+                chars.next();
+            }
+
+            let mut value = String::new();
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => if let Some(c) = chars.next() { value.push(c) },
+                        c => value.push(c)
+                    }
+                }
+                // Skip anything between the closing quote and the next `;`.
+                while chars.peek().map_or(false, |&c| c != ';') {
+                    chars.next();
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c == ';' {
+                        break
+                    }
+                    value.push(c);
+                    chars.next();
+                }
+                value = value.trim().to_string();
+            }
+            Some(value)
+        } else {
+            None
+        };
+
+        params.push((key.trim().to_string(), value));
+
+        if chars.next().is_none() {
+            return params
+        }
+    }
+}
+
+#[cfg(test)]
+fn upload(body: &str, max_part_size: u64) -> Result<Vec<(String, Option<String>, String)>, StatusCode> {
+    let mut multipart = Multipart::new(body.as_bytes(), "XyZ").max_part_size(max_part_size);
+    let mut parts = vec![];
+
+    loop {
+        let mut part = match multipart.next_part() {
+            Ok(Some(part)) => part,
+            Ok(None) => return Ok(parts),
+            Err((status, _)) => return Err(status)
+        };
+
+        let name = part.name().to_string();
+        let filename = part.filename().map(|f| f.to_string());
+        let text = try!(part.text().map_err(|(status, _)| status));
+        parts.push((name, filename, text));
+    }
+}
+
+#[test]
+fn parses_fields_and_files() {
+    let body = "preamble\r\n\
+                --XyZ\r\n\
+                Content-Disposition: form-data; name=\"title\"\r\n\
+                \r\n\
+                Holiday\r\n\
+                --XyZ\r\n\
+                Content-Disposition: form-data; name=\"photo\"; filename=\"beach; 1.jpg\"\r\n\
+                Content-Type: image/jpeg\r\n\
+                \r\n\
+                \r\n--X not a boundary\r\n\
+                --XyZ--\r\n\
+                epilogue";
+
+    let parts = upload(body, 1024).unwrap();
+    assert_eq!(parts, vec![
+        ("title".to_string(), None, "Holiday".to_string()),
+        ("photo".to_string(), Some("beach; 1.jpg".to_string()), "\r\n--X not a boundary".to_string())
+    ]);
+}
+
+#[test]
+fn rejects_invalid_and_large_bodies() {
+    let part = "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n0123456789\r\n";
+
+    assert!(upload(&format!("{}--XyZ--", part), 10).is_ok());
+    assert_eq!(upload(&format!("{}--XyZ--", part), 9), Err(StatusCode::PayloadTooLarge));
+    assert_eq!(upload(part, 10), Err(StatusCode::BadRequest));
+    assert_eq!(upload("no boundary at all", 10), Err(StatusCode::BadRequest));
+    assert_eq!(upload("--XyZ\r\nContent-Type: text/plain\r\n\r\nx\r\n--XyZ--", 10),
+               Err(StatusCode::BadRequest));
+}
+
+#[test]
+fn saves_files_until_dropped() {
+    let body = "--XyZ\r\n\
+                Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+                \r\n\
+                file content\r\n\
+                --XyZ--";
+
+    let mut multipart = Multipart::new(body.as_bytes(), "XyZ");
+    let path = {
+        let mut part = multipart.next_part().ok().unwrap().unwrap();
+        let file = part.save_temp().ok().unwrap();
+        assert_eq!(file.size(), 12);
+
+        let mut content = String::new();
+        File::open(file.path()).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "file content");
+        file.path().to_path_buf()
+    };
+
+    assert!(!path.exists());
+    assert!(multipart.next_part().ok().unwrap().is_none());
+}
+
+#[test]
+fn reads_bodies_kept_by_raw_body() {
+    use hyper::method::Method;
+    use body::RawBody;
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use testing::{TestClient, TestRequest};
+
+    let mut server = Nickel::new();
+    server.post("/", middleware! { |req, res|
+        let length = try_with!(res, req.raw_body()).len();
+        let mut multipart = try_with!(res, req.multipart());
+        let mut part = try_with!(res, multipart.next_part()).unwrap();
+        let text = try_with!(res, part.text());
+        format!("{} {}={}", length, part.name(), text)
+    });
+
+    let body = "--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHoliday\r\n--XyZ--";
+    let req = TestRequest::new(Method::Post, "/")
+                  .header(ContentType("multipart/form-data; boundary=XyZ".parse().unwrap()))
+                  .body(body);
+
+    let client = TestClient::new(server);
+    assert_eq!(client.dispatch(req).body_str(), Some(&*format!("{} title=Holiday", body.len())));
+}
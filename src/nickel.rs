@@ -1,17 +1,31 @@
-use std::net::ToSocketAddrs;
+use std::any::Any;
+use std::net::{ToSocketAddrs, TcpListener};
+use std::path::Path;
+#[cfg(unix)] use std::os::unix::io::{RawFd, FromRawFd};
 use router::{Router, HttpRouter, Matcher};
 use middleware::{MiddlewareStack, Middleware, ErrorHandler};
-use server::Server;
+use mount::Mount;
+use cookies;
+use server::{Server, ServerConfig, ServerData, ListeningServer};
+use tcp_listener::TcpSocketListener;
+use hyper::Result as HttpResult;
+use hyper::net::NetworkListener;
 use hyper::method::Method;
 use hyper::status::StatusCode;
 
 //pre defined middleware
 use default_error_handler::DefaultErrorHandler;
+#[cfg(unix)] use unix_listener::{self, UnixSocketListener, SD_LISTEN_FDS_START};
+#[cfg(unix)] use unix_socket::UnixListener;
+#[cfg(unix)] use libc;
 
 /// Nickel is the application object. It's the surface that
 /// holds all public APIs.
 pub struct Nickel{
     middleware_stack: MiddlewareStack,
+    config: ServerConfig,
+    data: Box<ServerData>,
+    secret_key: Option<Vec<u8>>
 }
 
 impl HttpRouter for Nickel {
@@ -26,6 +40,32 @@ impl HttpRouter for Nickel {
 impl Nickel {
     /// Creates an instance of Nickel with default error handling.
     pub fn new() -> Nickel {
+        Nickel::with_data(())
+    }
+
+    /// Creates an instance of Nickel with default error handling, sharing
+    /// `data` with all handlers and error handlers through
+    /// `Request::server_data`.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter};
+    ///
+    /// struct Config {
+    ///     greeting: String
+    /// }
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::with_data(Config { greeting: "Hello".to_string() });
+    ///
+    ///     server.get("/", middleware! { |req|
+    ///         let config = req.server_data::<Config>().unwrap();
+    ///         format!("{} World", config.greeting)
+    ///     });
+    /// }
+    /// ```
+    pub fn with_data<D: Any + Send + Sync>(data: D) -> Nickel {
         let mut middleware_stack = MiddlewareStack::new();
 
         // Hook up the default error handler by default. Users are
@@ -33,7 +73,12 @@ impl Nickel {
         // they don't like the default behaviour.
         middleware_stack.add_error_handler(DefaultErrorHandler);
 
-        Nickel { middleware_stack: middleware_stack }
+        Nickel {
+            middleware_stack: middleware_stack,
+            config: ServerConfig::new(),
+            data: Box::new(data),
+            secret_key: None
+        }
     }
 
     /// Registers a middleware handler which will be invoked among other middleware
@@ -61,6 +106,24 @@ impl Nickel {
         self.middleware_stack.add_middleware(handler);
     }
 
+    /// Registers a middleware handler which only sees requests below
+    /// `mount_point`, with the mount point stripped from the request path.
+    ///
+    /// See `Mount` for details.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// use nickel::{Nickel, StaticFilesHandler};
+    ///
+    /// let mut server = Nickel::new();
+    ///
+    /// // serves /static/logo.png from assets/logo.png
+    /// server.mount("/static", StaticFilesHandler::new("assets/"));
+    /// ```
+    pub fn mount<S: AsRef<str>, T: Middleware>(&mut self, mount_point: S, handler: T) {
+        self.utilize(Mount::new(mount_point, handler));
+    }
+
     /// Registers an error handler which will be invoked among other error handler
     /// as soon as any regular handler returned an error
     ///
@@ -122,6 +185,28 @@ impl Nickel {
         Router::new()
     }
 
+    /// Sets the key used to sign and encrypt cookies, see
+    /// `Response::set_signed_cookie` and `Response::set_encrypted_cookie`.
+    ///
+    /// Without a key, a random one is generated when the server starts and
+    /// cookies signed by a previous run of the application are rejected.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// use nickel::Nickel;
+    ///
+    /// let mut server = Nickel::new();
+    /// server.secret_key("a long, random string kept out of version control");
+    /// ```
+    pub fn secret_key<K: Into<Vec<u8>>>(&mut self, key: K) {
+        self.secret_key = Some(key.into());
+    }
+
+    /// Replaces the settings used for the HTTP server, see `ServerConfig`.
+    pub fn configure(&mut self, config: ServerConfig) {
+        self.config = config;
+    }
+
     /// Bind and listen for connections on the given host and port.
     ///
     /// # Panics
@@ -135,17 +220,199 @@ impl Nickel {
     /// let server = Nickel::new();
     /// server.listen("127.0.0.1:6767");
     /// ```
-    pub fn listen<T: ToSocketAddrs>(mut self, addr: T) {
-        self.middleware_stack.add_middleware(middleware! {
-            (StatusCode::NotFound, "File Not Found")
-        });
+    pub fn listen<T: ToSocketAddrs>(self, addr: T) {
+        let server = self.start(addr).unwrap();
 
-        let server = Server::new(self.middleware_stack);
-        let listener = server.serve(addr).unwrap();
+        println!("Listening on http://{}", server.socket());
+        println!("Ctrl-C to shutdown server");
+
+        server.wait();
+    }
+
+    /// Bind and start listening for connections on the given host and port
+    /// without blocking the current thread.
+    ///
+    /// The returned `ListeningServer` can be used to stop the server again.
+    ///
+    /// # Examples
+    /// ```{rust,no_run}
+    /// use std::time::Duration;
+    /// use nickel::Nickel;
+    ///
+    /// let server = Nickel::new().start("127.0.0.1:6767").unwrap();
+    /// println!("Listening on http://{}", server.socket());
+    ///
+    /// // Stop accepting requests, giving running ones 10 seconds to finish
+    /// server.shutdown(Duration::from_secs(10));
+    /// ```
+    pub fn start<T: ToSocketAddrs>(self, addr: T) -> HttpResult<ListeningServer> {
+        into_server(self).serve(addr)
+    }
+
+    /// Bind and listen for TLS connections on the given host and port,
+    /// using the PEM encoded certificate and private key at the given paths.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `addr` is an invalid `SocketAddr` or if the certificate
+    /// or private key can't be loaded.
+    ///
+    /// # Examples
+    /// ```{rust,no_run}
+    /// use nickel::Nickel;
+    ///
+    /// let server = Nickel::new();
+    /// server.listen_https("127.0.0.1:6767", "cert.pem", "key.pem");
+    /// ```
+    pub fn listen_https<T, C, K>(self, addr: T, cert: C, key: K)
+            where T: ToSocketAddrs, C: AsRef<Path>, K: AsRef<Path> {
+        let server = self.start_https(addr, cert, key).unwrap();
 
-        println!("Listening on http://{}", listener.socket);
+        println!("Listening on https://{}", server.socket());
         println!("Ctrl-C to shutdown server");
+
+        server.wait();
+    }
+
+    /// The non-blocking counterpart of `listen_https`, see `start` for details.
+    pub fn start_https<T, C, K>(self, addr: T, cert: C, key: K) -> HttpResult<ListeningServer>
+            where T: ToSocketAddrs, C: AsRef<Path>, K: AsRef<Path> {
+        into_server(self).serve_https(addr, cert.as_ref(), key.as_ref())
     }
+
+    /// Listen for connections on a Unix domain socket at the given path.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the socket can't be created, for example because the
+    /// path already exists.
+    ///
+    /// # Examples
+    /// ```{rust,no_run}
+    /// use nickel::Nickel;
+    ///
+    /// let server = Nickel::new();
+    /// server.listen_unix("/tmp/nickel.sock");
+    /// ```
+    #[cfg(unix)]
+    pub fn listen_unix<P: AsRef<Path>>(self, path: P) {
+        let path = path.as_ref();
+        let listener = UnixSocketListener::bind(path).unwrap();
+        let server = self.start_on(listener).unwrap();
+
+        println!("Listening on unix:{}", path.display());
+        println!("Ctrl-C to shutdown server");
+
+        server.wait();
+    }
+
+    /// Listen for connections on an already bound `TcpListener`.
+    ///
+    /// # Examples
+    /// ```{rust,no_run}
+    /// use std::net::TcpListener;
+    /// use nickel::Nickel;
+    ///
+    /// let listener = TcpListener::bind("127.0.0.1:6767").unwrap();
+    /// let server = Nickel::new();
+    /// server.listen_tcp(listener);
+    /// ```
+    pub fn listen_tcp(self, listener: TcpListener) {
+        let server = self.start_on(TcpSocketListener::from(listener)).unwrap();
+
+        println!("Listening on http://{}", server.socket());
+        println!("Ctrl-C to shutdown server");
+
+        server.wait();
+    }
+
+    /// Listen for connections on a bound TCP or Unix domain socket
+    /// inherited from the parent process, as passed by einhorn.
+    ///
+    /// The server takes ownership of `fd`, it must not be used or closed
+    /// elsewhere.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fd` isn't a TCP or Unix domain socket.
+    ///
+    /// # Examples
+    /// ```{rust,no_run}
+    /// use nickel::Nickel;
+    ///
+    /// let server = Nickel::new();
+    /// server.listen_fd(3);
+    /// ```
+    #[cfg(unix)]
+    pub fn listen_fd(self, fd: RawFd) {
+        match unix_listener::socket_family(fd) {
+            Ok(libc::AF_INET) | Ok(libc::AF_INET6) => {
+                self.listen_tcp(unsafe { TcpListener::from_raw_fd(fd) })
+            },
+            Ok(libc::AF_UNIX) => {
+                let listener = unsafe { UnixListener::from_raw_fd(fd) };
+                let server = self.start_on(UnixSocketListener::from(listener)).unwrap();
+
+                println!("Listening on unix socket fd {}", fd);
+                println!("Ctrl-C to shutdown server");
+
+                server.wait();
+            },
+            Ok(family) => panic!("File descriptor {} has unsupported address family {}", fd, family),
+            Err(e) => panic!("File descriptor {} is not a socket: {}", fd, e)
+        }
+    }
+
+    /// Listen for connections on the first socket passed by systemd socket
+    /// activation, checking `LISTEN_PID` and `LISTEN_FDS`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if systemd didn't pass a socket to this process, or if it
+    /// isn't a TCP or Unix domain socket.
+    ///
+    /// # Examples
+    /// ```{rust,no_run}
+    /// use nickel::Nickel;
+    ///
+    /// let server = Nickel::new();
+    /// server.listen_systemd();
+    /// ```
+    #[cfg(unix)]
+    pub fn listen_systemd(self) {
+        if unix_listener::systemd_listen_fds() == 0 {
+            panic!("No socket was passed by systemd, LISTEN_PID or LISTEN_FDS is not set")
+        }
+
+        self.listen_fd(SD_LISTEN_FDS_START)
+    }
+
+    /// Start serving connections from any hyper `NetworkListener` without
+    /// blocking the current thread, see `start` for details.
+    ///
+    /// # Examples
+    /// ```{rust,no_run}
+    /// use nickel::{Nickel, TcpSocketListener};
+    ///
+    /// let listener = TcpSocketListener::bind("127.0.0.1:6767").unwrap();
+    /// let server = Nickel::new().start_on(listener).unwrap();
+    /// # server.close();
+    /// ```
+    pub fn start_on<L>(self, listener: L) -> HttpResult<ListeningServer>
+            where L: NetworkListener + Send + 'static {
+        into_server(self).serve_on(listener)
+    }
+}
+
+// Finishes the middleware stack and builds the `Server` running it. This is
+// shared by the `listen` methods and `testing::TestClient`.
+pub fn into_server(mut app: Nickel) -> Server {
+    app.middleware_stack.add_middleware(middleware! {
+        (StatusCode::NotFound, "File Not Found")
+    });
+
+    let secret_key = app.secret_key.unwrap_or_else(cookies::random_key);
+    Server::new(app.middleware_stack, app.config, app.data, secret_key)
 }
 
 #[test]
@@ -153,3 +420,53 @@ impl Nickel {
 fn invalid_listen_addr() {
     Nickel::new().listen("127.0.0.1.6667");
 }
+
+#[test]
+fn start_and_shutdown() {
+    use std::time::Duration;
+
+    use std::net::TcpStream;
+
+    let server = Nickel::new().start("127.0.0.1:0").unwrap();
+    let addr = server.socket();
+    assert!(TcpStream::connect(addr).is_ok());
+    assert!(server.shutdown(Duration::from_secs(1)));
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn refuses_to_start_https_without_a_certificate() {
+    let result = Nickel::new().start_https("127.0.0.1:0", "missing-cert.pem", "missing-key.pem");
+    assert!(result.is_err());
+}
+
+#[test]
+fn shares_server_data_with_handlers() {
+    use std::io::Write;
+    use middleware::{Action, Halt};
+    use nickel_error::NickelError;
+    use request::Request;
+    use testing::TestClient;
+
+    struct Greeting(&'static str);
+
+    fn handle_error(err: &mut NickelError, req: &mut Request) -> Action {
+        if let Some(ref mut res) = err.stream {
+            let greeting = req.server_data::<Greeting>().unwrap();
+            let _ = write!(res, "{} from the error handler", greeting.0);
+        }
+        Halt(())
+    }
+
+    let mut server = Nickel::with_data(Greeting("Hello"));
+    server.get("/", middleware! { |req|
+        assert!(req.server_data::<String>().is_none());
+        format!("{} from a handler", req.server_data::<Greeting>().unwrap().0)
+    });
+    let handler: fn(&mut NickelError, &mut Request) -> Action = handle_error;
+    server.handle_error(handler);
+
+    let client = TestClient::new(server);
+    assert_eq!(client.get("/").body_str(), Some("Hello from a handler"));
+    assert_eq!(client.get("/missing").body_str(), Some("Hello from the error handler"));
+}
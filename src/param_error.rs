@@ -0,0 +1,69 @@
+use std::error::Error;
+use std::fmt;
+use hyper::status::StatusCode;
+
+/// A route or query parameter which is missing or can't be parsed.
+#[derive(Debug)]
+pub struct ParamError {
+    name: String,
+    value: Option<String>,
+    description: String
+}
+
+impl ParamError {
+    /// The parameter `name` isn't present.
+    pub fn missing(name: &str) -> ParamError {
+        ParamError {
+            name: name.to_string(),
+            value: None,
+            description: format!("Missing parameter '{}'", name)
+        }
+    }
+
+    /// The parameter `name` is present, but `value` isn't valid.
+    pub fn invalid(name: &str, value: &str) -> ParamError {
+        ParamError {
+            name: name.to_string(),
+            value: Some(value.to_string()),
+            description: format!("Invalid value '{}' for parameter '{}'", value, name)
+        }
+    }
+
+    /// The name of the parameter.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The value which failed to parse, or `None` if the parameter is
+    /// missing.
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_ref().map(|value| &**value)
+    }
+}
+
+// An error about the parameter `name` described by a decoder.
+pub fn with_description(name: &str, description: String) -> ParamError {
+    ParamError {
+        name: name.to_string(),
+        value: None,
+        description: description
+    }
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.description)
+    }
+}
+
+impl Error for ParamError {
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+impl From<ParamError> for (StatusCode, ParamError) {
+    fn from(err: ParamError) -> (StatusCode, ParamError) {
+        (StatusCode::BadRequest, err)
+    }
+}
@@ -0,0 +1,218 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::{self, FromStr};
+use hyper::header::Headers;
+
+/// An address or CIDR range of proxies whose `Forwarded` and
+/// `X-Forwarded-*` headers are trusted, see `ServerConfig::trust_proxy`.
+///
+/// # Examples
+/// ```{rust}
+/// use nickel::TrustedProxy;
+///
+/// let proxy: TrustedProxy = "10.0.0.0/8".parse().unwrap();
+/// assert!(proxy.contains("10.1.2.3".parse().unwrap()));
+///
+/// let proxy: TrustedProxy = "::1".parse().unwrap();
+/// assert!(!proxy.contains("::2".parse().unwrap()));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrustedProxy {
+    addr: IpAddr,
+    prefix: u8
+}
+
+impl TrustedProxy {
+    /// Whether `ip` is part of the range. IPv4-mapped IPv6 addresses match
+    /// their IPv4 counterpart.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, unmap(ip)) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                prefix_matches(&range.octets(), &ip.octets(), self.prefix)
+            },
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                prefix_matches(&octets(&range.segments()), &octets(&ip.segments()), self.prefix)
+            },
+            _ => false
+        }
+    }
+}
+
+impl FromStr for TrustedProxy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<TrustedProxy, &'static str> {
+        let mut parts = s.splitn(2, '/');
+        let addr = try!(parts.next().unwrap().parse::<IpAddr>()
+                                             .map_err(|_| "Not a valid IP address."));
+        let addr = unmap(addr);
+        let max_prefix = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128
+        };
+
+        let prefix = match parts.next() {
+            Some(prefix) => try!(prefix.parse::<u8>().map_err(|_| "Not a valid prefix length.")),
+            None => max_prefix
+        };
+
+        if prefix > max_prefix {
+            return Err("Not a valid prefix length.")
+        }
+
+        Ok(TrustedProxy { addr: addr, prefix: prefix })
+    }
+}
+
+fn prefix_matches(range: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let (bytes, bits) = ((prefix / 8) as usize, prefix % 8);
+    if range[..bytes] != ip[..bytes] {
+        return false
+    }
+
+    bits == 0 || (range[bytes] ^ ip[bytes]) >> (8 - bits) == 0
+}
+
+fn octets(segments: &[u16]) -> Vec<u8> {
+    segments.iter().flat_map(|&s| vec![(s >> 8) as u8, s as u8]).collect()
+}
+
+// Dual-stack sockets report IPv4 peers as `::ffff:a.b.c.d`.
+fn unmap(ip: IpAddr) -> IpAddr {
+    if let IpAddr::V6(v6) = ip {
+        let s = v6.segments();
+        if s[..6] == [0, 0, 0, 0, 0, 0xffff] {
+            return IpAddr::V4(Ipv4Addr::new((s[6] >> 8) as u8, s[6] as u8,
+                                            (s[7] >> 8) as u8, s[7] as u8))
+        }
+    }
+
+    ip
+}
+
+/// The client, scheme and host of a request, as reported by the proxies
+/// in front of the server.
+pub struct Forwarded<'a> {
+    pub client: IpAddr,
+    pub proto: Option<&'a str>,
+    pub host: Option<&'a str>
+}
+
+/// Walks the forwarding headers from the nearest proxy outwards, for as
+/// long as the hops are trusted. The first untrusted hop is the client.
+///
+/// `Forwarded` takes precedence over the `X-Forwarded-*` headers. Headers
+/// are ignored entirely unless `peer` is trusted, since anyone can send
+/// them.
+pub fn resolve<'a>(headers: &'a Headers,
+                   peer: IpAddr,
+                   trusted: &[TrustedProxy]) -> Forwarded<'a> {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|proxy| proxy.contains(ip));
+
+    let mut forwarded = Forwarded { client: unmap(peer), proto: None, host: None };
+    if !is_trusted(peer) {
+        return forwarded
+    }
+
+    let elements = values(headers, "Forwarded");
+    if !elements.is_empty() {
+        // each element describes the request received by the hop that
+        // added it, so the scheme and host come from the last one used
+        for element in elements.iter().rev() {
+            let mut client = None;
+            forwarded.proto = None;
+            forwarded.host = None;
+
+            for pair in element.split(';') {
+                let mut pair = pair.splitn(2, '=');
+                let name = pair.next().unwrap().trim().to_lowercase();
+                let value = unquote(pair.next().unwrap_or("").trim());
+                match &*name {
+                    "for" => client = parse_node(value),
+                    "proto" => forwarded.proto = Some(value),
+                    "host" => forwarded.host = Some(value),
+                    _ => {}
+                }
+            }
+
+            match client {
+                Some(ip) => forwarded.client = unmap(ip),
+                // obfuscated or unknown, the hop is all we know
+                None => break
+            }
+
+            if !is_trusted(forwarded.client) {
+                break
+            }
+        }
+    } else {
+        for node in values(headers, "X-Forwarded-For").iter().rev() {
+            match parse_node(node) {
+                Some(ip) => forwarded.client = unmap(ip),
+                None => break
+            }
+
+            if !is_trusted(forwarded.client) {
+                break
+            }
+        }
+
+        // set by the nearest proxy, the ones further out can't be told apart
+        forwarded.proto = values(headers, "X-Forwarded-Proto").pop();
+        forwarded.host = values(headers, "X-Forwarded-Host").pop();
+    }
+
+    forwarded
+}
+
+// The comma separated values of all `name` headers, in order.
+fn values<'a>(headers: &'a Headers, name: &str) -> Vec<&'a str> {
+    headers.get_raw(name).map(|lines| {
+        lines.iter()
+             .filter_map(|line| str::from_utf8(line).ok())
+             .flat_map(|line| line.split(','))
+             .map(|value| value.trim())
+             .filter(|value| !value.is_empty())
+             .collect()
+    }).unwrap_or(vec![])
+}
+
+fn unquote(value: &str) -> &str {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
+
+// Parses `1.2.3.4`, `1.2.3.4:80`, `::1` and `[::1]:80`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let addr = if node.starts_with('[') {
+        node[1..].splitn(2, ']').next().unwrap()
+    } else if node.matches(':').count() == 1 {
+        node.splitn(2, ':').next().unwrap()
+    } else {
+        node
+    };
+
+    addr.parse().ok()
+}
+
+#[test]
+fn matches_addresses_and_ranges() {
+    let t = |proxy: &str, ip: &str| {
+        proxy.parse::<TrustedProxy>().unwrap().contains(ip.parse().unwrap())
+    };
+
+    assert!(t("10.0.0.1", "10.0.0.1"));
+    assert!(!t("10.0.0.1", "10.0.0.2"));
+    assert!(t("172.16.0.0/12", "172.31.255.255"));
+    assert!(!t("172.16.0.0/12", "172.32.0.0"));
+    assert!(t("0.0.0.0/0", "8.8.8.8"));
+    assert!(t("fd00::/8", "fd12:3456::1"));
+    assert!(!t("fd00::/8", "fe80::1"));
+    assert!(t("127.0.0.1", "::ffff:127.0.0.1"));
+    assert!(!t("::1", "127.0.0.1"));
+
+    assert!("10.0.0.0/33".parse::<TrustedProxy>().is_err());
+    assert!("localhost".parse::<TrustedProxy>().is_err());
+}
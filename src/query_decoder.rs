@@ -0,0 +1,339 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use serialize::{Decodable, Decoder};
+
+use param_error::{self, ParamError};
+
+// The parameters of a query as a tree, splitting keys like `filter[status]`
+// at the brackets.
+#[derive(Default)]
+struct Node<'a> {
+    values: Vec<&'a str>,
+    children: BTreeMap<&'a str, Node<'a>>
+}
+
+#[derive(Clone, Copy)]
+enum Value<'a> {
+    Node(&'a Node<'a>),
+    Str(&'a str),
+    Missing
+}
+
+/// Decodes the parameters of a query into a `T`.
+///
+/// Struct fields are looked up by name, nested structs and maps with
+/// bracketed keys (`filter[status]=open`) and sequences from repeated keys
+/// (`tag=a&tag=b` or `tag[]=a&tag[]=b`). Missing and empty values decode to
+/// `None` for `Option` fields and missing ones to an empty `Vec`.
+pub fn decode<T: Decodable>(params: &HashMap<String, Vec<String>>) -> Result<T, ParamError> {
+    let mut root = Node::default();
+    for (key, values) in params {
+        let node = segments(key).into_iter().fold(&mut root, |node, segment| {
+            node.children.entry(segment).or_insert_with(Node::default)
+        });
+        node.values.extend(values.iter().map(|value| &**value));
+    }
+
+    T::decode(&mut QueryDecoder { current: Value::Node(&root), name: String::new() })
+}
+
+// `a[b][c]` is `a`, `b`, `c` and `tags[]` is just `tags`. Keys with
+// unbalanced brackets are kept as they are.
+fn segments(key: &str) -> Vec<&str> {
+    let mut parts = key.split('[');
+    let mut segments = vec![parts.next().unwrap()];
+
+    for part in parts {
+        match part.find(']') {
+            Some(0) => {},
+            Some(end) if end == part.len() - 1 => segments.push(&part[..end]),
+            _ => return vec![key]
+        }
+    }
+
+    segments
+}
+
+struct QueryDecoder<'a> {
+    current: Value<'a>,
+    // the key of the current value, as sent by the client
+    name: String
+}
+
+impl<'a> QueryDecoder<'a> {
+    fn with<T, F>(&mut self, value: Value<'a>, name: String, f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>) -> Result<T, ParamError> {
+        let parent = (self.current, self.name.clone());
+        self.current = value;
+        self.name = name;
+        let result = f(self);
+        self.current = parent.0;
+        self.name = parent.1;
+        result
+    }
+
+    fn child_name(&self, key: &str) -> String {
+        if self.name.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}[{}]", self.name, key)
+        }
+    }
+
+    fn value(&self) -> Result<&'a str, ParamError> {
+        match self.current {
+            Value::Str(value) => Ok(value),
+            Value::Node(node) => node.values.first().cloned().ok_or_else(|| self.missing()),
+            Value::Missing => Err(self.missing())
+        }
+    }
+
+    fn parse<T: FromStr>(&self) -> Result<T, ParamError> {
+        let value = try!(self.value());
+        value.parse().map_err(|_| ParamError::invalid(&self.name, value))
+    }
+
+    fn missing(&self) -> ParamError {
+        ParamError::missing(&self.name)
+    }
+
+    fn element(&self, idx: usize) -> Value<'a> {
+        match self.current {
+            Value::Node(node) => node.values.get(idx).map_or(Value::Missing, |&v| Value::Str(v)),
+            Value::Str(value) if idx == 0 => Value::Str(value),
+            _ => Value::Missing
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self.current {
+            Value::Node(node) => node.values.len(),
+            Value::Str(_) => 1,
+            Value::Missing => 0
+        }
+    }
+}
+
+impl<'a> Decoder for QueryDecoder<'a> {
+    type Error = ParamError;
+
+    fn read_nil(&mut self) -> Result<(), ParamError> { Ok(()) }
+    fn read_usize(&mut self) -> Result<usize, ParamError> { self.parse() }
+    fn read_u64(&mut self) -> Result<u64, ParamError> { self.parse() }
+    fn read_u32(&mut self) -> Result<u32, ParamError> { self.parse() }
+    fn read_u16(&mut self) -> Result<u16, ParamError> { self.parse() }
+    fn read_u8(&mut self) -> Result<u8, ParamError> { self.parse() }
+    fn read_isize(&mut self) -> Result<isize, ParamError> { self.parse() }
+    fn read_i64(&mut self) -> Result<i64, ParamError> { self.parse() }
+    fn read_i32(&mut self) -> Result<i32, ParamError> { self.parse() }
+    fn read_i16(&mut self) -> Result<i16, ParamError> { self.parse() }
+    fn read_i8(&mut self) -> Result<i8, ParamError> { self.parse() }
+    fn read_f64(&mut self) -> Result<f64, ParamError> { self.parse() }
+    fn read_f32(&mut self) -> Result<f32, ParamError> { self.parse() }
+
+    fn read_bool(&mut self) -> Result<bool, ParamError> {
+        // HTML checkboxes send `on`
+        match try!(self.value()) {
+            "true" | "on" | "1" => Ok(true),
+            "false" | "off" | "0" => Ok(false),
+            value => Err(ParamError::invalid(&self.name, value))
+        }
+    }
+
+    fn read_char(&mut self) -> Result<char, ParamError> {
+        let value = try!(self.value());
+        let mut chars = value.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(c),
+            _ => Err(ParamError::invalid(&self.name, value))
+        }
+    }
+
+    fn read_str(&mut self) -> Result<String, ParamError> {
+        self.value().map(|value| value.to_string())
+    }
+
+    fn read_enum<T, F>(&mut self, _name: &str, f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>) -> Result<T, ParamError> {
+        f(self)
+    }
+
+    fn read_enum_variant<T, F>(&mut self, names: &[&str], mut f: F) -> Result<T, ParamError>
+            where F: FnMut(&mut QueryDecoder<'a>, usize) -> Result<T, ParamError> {
+        let value = try!(self.value());
+        match names.iter().position(|name| *name == value) {
+            Some(idx) => f(self, idx),
+            None => Err(ParamError::invalid(&self.name, value))
+        }
+    }
+
+    fn read_enum_variant_arg<T, F>(&mut self, _idx: usize, _f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>) -> Result<T, ParamError> {
+        Err(self.error("Enum variants with fields can't be decoded from a query"))
+    }
+
+    fn read_enum_struct_variant<T, F>(&mut self, names: &[&str], f: F) -> Result<T, ParamError>
+            where F: FnMut(&mut QueryDecoder<'a>, usize) -> Result<T, ParamError> {
+        self.read_enum_variant(names, f)
+    }
+
+    fn read_enum_struct_variant_field<T, F>(&mut self, _name: &str, idx: usize, f: F)
+            -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>) -> Result<T, ParamError> {
+        self.read_enum_variant_arg(idx, f)
+    }
+
+    fn read_struct<T, F>(&mut self, _name: &str, _len: usize, f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>) -> Result<T, ParamError> {
+        f(self)
+    }
+
+    fn read_struct_field<T, F>(&mut self, name: &str, _idx: usize, f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>) -> Result<T, ParamError> {
+        let value = match self.current {
+            Value::Node(node) => node.children.get(name).map_or(Value::Missing, Value::Node),
+            _ => Value::Missing
+        };
+        let name = self.child_name(name);
+        self.with(value, name, f)
+    }
+
+    fn read_tuple<T, F>(&mut self, len: usize, f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>) -> Result<T, ParamError> {
+        if self.len() != len {
+            return Err(self.error(&format!("Expected {} values", len)))
+        }
+        f(self)
+    }
+
+    fn read_tuple_arg<T, F>(&mut self, idx: usize, f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>) -> Result<T, ParamError> {
+        self.read_seq_elt(idx, f)
+    }
+
+    fn read_tuple_struct<T, F>(&mut self, _name: &str, len: usize, f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>) -> Result<T, ParamError> {
+        self.read_tuple(len, f)
+    }
+
+    fn read_tuple_struct_arg<T, F>(&mut self, idx: usize, f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>) -> Result<T, ParamError> {
+        self.read_seq_elt(idx, f)
+    }
+
+    fn read_option<T, F>(&mut self, mut f: F) -> Result<T, ParamError>
+            where F: FnMut(&mut QueryDecoder<'a>, bool) -> Result<T, ParamError> {
+        let present = match self.current {
+            Value::Node(node) => {
+                node.values.iter().any(|value| !value.is_empty()) || !node.children.is_empty()
+            },
+            Value::Str(value) => !value.is_empty(),
+            Value::Missing => false
+        };
+        f(self, present)
+    }
+
+    fn read_seq<T, F>(&mut self, f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>, usize) -> Result<T, ParamError> {
+        let len = self.len();
+        f(self, len)
+    }
+
+    fn read_seq_elt<T, F>(&mut self, idx: usize, f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>) -> Result<T, ParamError> {
+        let (value, name) = (self.element(idx), self.name.clone());
+        self.with(value, name, f)
+    }
+
+    fn read_map<T, F>(&mut self, f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>, usize) -> Result<T, ParamError> {
+        let len = match self.current {
+            Value::Node(node) => node.children.len(),
+            _ => 0
+        };
+        f(self, len)
+    }
+
+    fn read_map_elt_key<T, F>(&mut self, idx: usize, f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>) -> Result<T, ParamError> {
+        let key = match self.current {
+            Value::Node(node) => node.children.keys().nth(idx).map_or(Value::Missing, |&k| Value::Str(k)),
+            _ => Value::Missing
+        };
+        let name = self.name.clone();
+        self.with(key, name, f)
+    }
+
+    fn read_map_elt_val<T, F>(&mut self, idx: usize, f: F) -> Result<T, ParamError>
+            where F: FnOnce(&mut QueryDecoder<'a>) -> Result<T, ParamError> {
+        let (value, name) = match self.current {
+            Value::Node(node) => match node.children.iter().nth(idx) {
+                Some((key, child)) => (Value::Node(child), self.child_name(key)),
+                None => (Value::Missing, self.name.clone())
+            },
+            _ => (Value::Missing, self.name.clone())
+        };
+        self.with(value, name, f)
+    }
+
+    fn error(&mut self, err: &str) -> ParamError {
+        param_error::with_description(&self.name, err.to_string())
+    }
+}
+
+#[test]
+fn decodes_structs() {
+    use hyper::status::StatusCode;
+    use query_string;
+
+    struct Filter { status: String, assignee: Option<String> }
+
+    impl Decodable for Filter {
+        fn decode<D: Decoder>(d: &mut D) -> Result<Filter, D::Error> {
+            d.read_struct("Filter", 2, |d| Ok(Filter {
+                status: try!(d.read_struct_field("status", 0, Decodable::decode)),
+                assignee: try!(d.read_struct_field("assignee", 1, Decodable::decode))
+            }))
+        }
+    }
+
+    struct Search {
+        page: u32,
+        per_page: Option<u32>,
+        draft: bool,
+        tag: Vec<String>,
+        filter: Filter,
+        extra: HashMap<String, String>
+    }
+
+    impl Decodable for Search {
+        fn decode<D: Decoder>(d: &mut D) -> Result<Search, D::Error> {
+            d.read_struct("Search", 6, |d| Ok(Search {
+                page: try!(d.read_struct_field("page", 0, Decodable::decode)),
+                per_page: try!(d.read_struct_field("per_page", 1, Decodable::decode)),
+                draft: try!(d.read_struct_field("draft", 2, Decodable::decode)),
+                tag: try!(d.read_struct_field("tag", 3, Decodable::decode)),
+                filter: try!(d.read_struct_field("filter", 4, Decodable::decode)),
+                extra: try!(d.read_struct_field("extra", 5, Decodable::decode))
+            }))
+        }
+    }
+
+    let t = |query: &str| query_string::from_form_body(query.as_bytes()).decode::<Search>();
+
+    let search = t("page=2&per_page=&draft=on&tag[]=a&tag[]=b\
+                    &filter[status]=open&extra[x]=1&extra[y]=2").ok().unwrap();
+    assert_eq!(search.page, 2);
+    assert_eq!(search.per_page, None);
+    assert!(search.draft);
+    assert_eq!(search.tag, vec!["a".to_string(), "b".to_string()]);
+    assert_eq!((&*search.filter.status, search.filter.assignee), ("open", None));
+    assert_eq!(search.extra.get("y").map(|y| &**y), Some("2"));
+
+    let (status, err) = t("page=two&draft=0&filter[status]=open").err().unwrap();
+    assert_eq!(status, StatusCode::BadRequest);
+    assert_eq!((err.name(), err.value()), ("page", Some("two")));
+
+    let (_, err) = t("page=1&draft=0").err().unwrap();
+    assert_eq!((err.name(), err.value()), ("filter[status]", None));
+}
@@ -1,4 +1,9 @@
 use std::collections::HashMap;
+use std::str::FromStr;
+use hyper::status::StatusCode;
+use param_error::ParamError;
+use query_decoder;
+use serialize::Decodable;
 use request::Request;
 use urlencoded;
 use hyper::uri::RequestUri;
@@ -27,6 +32,39 @@ impl Query {
     pub fn all(&self, key: &str) -> Option<&[String]> {
         self.0.get(key).map(|v| &**v)
     }
+
+    /// Parses the first value from the query for `key` into a `T`.
+    ///
+    /// Fails with `400 Bad Request` if there's no value or it can't be
+    /// parsed, which `try_with!` turns into a `NickelError`.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter, QueryString};
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     server.get("/articles", middleware! { |req, res|
+    ///         let page = try_with!(res, req.query().get_as::<u32>("page"));
+    ///         format!("Page {}", page)
+    ///     });
+    /// }
+    /// ```
+    pub fn get_as<T: FromStr>(&self, key: &str) -> Result<T, (StatusCode, ParamError)> {
+        match self.get(key) {
+            Some(value) => value.parse().map_err(|_| ParamError::invalid(key, value).into()),
+            None => Err(ParamError::missing(key).into())
+        }
+    }
+
+    /// Decodes the whole query into a `T`, see `QueryString::query_as`.
+    ///
+    /// Fails with `400 Bad Request`, naming the parameter which is missing
+    /// or can't be parsed.
+    pub fn decode<T: Decodable>(&self) -> Result<T, (StatusCode, ParamError)> {
+        query_decoder::decode(&self.0).map_err(From::from)
+    }
 }
 
 // Plugin boilerplate
@@ -44,6 +82,48 @@ impl<'mw, 'conn> Plugin<Request<'mw, 'conn>> for QueryStringParser {
 pub trait QueryString {
     /// Retrieve the query from the current `Request`.
     fn query(&mut self) -> &Query;
+
+    /// Decodes the query of the current `Request` into a `T`.
+    ///
+    /// Fields are looked up by name. Numbers and booleans are parsed,
+    /// `Option` fields are `None` for missing or empty parameters, repeated
+    /// keys (`tag=a&tag=b` or `tag[]=a&tag[]=b`) fill a `Vec` and bracketed
+    /// keys (`filter[status]=open`) fill nested structs and maps.
+    ///
+    /// Fails with `400 Bad Request`, naming the parameter which is missing
+    /// or can't be parsed, which `try_with!` turns into a `NickelError`.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// extern crate rustc_serialize;
+    /// use nickel::{Nickel, HttpRouter, QueryString};
+    ///
+    /// #[derive(RustcDecodable)]
+    /// struct Filter {
+    ///     status: Option<String>
+    /// }
+    ///
+    /// #[derive(RustcDecodable)]
+    /// struct Pagination {
+    ///     page: u32,
+    ///     per_page: Option<u32>,
+    ///     filter: Filter
+    /// }
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     // e.g. /issues?page=2&filter[status]=open
+    ///     server.get("/issues", middleware! { |req, res|
+    ///         let query = try_with!(res, req.query_as::<Pagination>());
+    ///         format!("Page {} of {} {} issues",
+    ///                 query.page,
+    ///                 query.per_page.unwrap_or(20),
+    ///                 query.filter.status.unwrap_or("all".to_string()))
+    ///     });
+    /// }
+    /// ```
+    fn query_as<T: Decodable>(&mut self) -> Result<T, (StatusCode, ParamError)>;
 }
 
 impl<'mw, 'conn> QueryString for Request<'mw, 'conn> {
@@ -52,6 +132,15 @@ impl<'mw, 'conn> QueryString for Request<'mw, 'conn> {
             .ok()
             .expect("Bug: QueryStringParser returned None")
     }
+
+    fn query_as<T: Decodable>(&mut self) -> Result<T, (StatusCode, ParamError)> {
+        self.query().decode()
+    }
+}
+
+// Builds a `Query` from an `application/x-www-form-urlencoded` body.
+pub fn from_form_body(body: &[u8]) -> Query {
+    Query(urlencoded::parse_bytes(body))
 }
 
 fn parse(origin: &RequestUri) -> Query {
@@ -94,3 +183,17 @@ fn splits_and_parses_an_url() {
     let store = parse(&Authority("host.com".to_string()));
     assert_eq!(store, Query(HashMap::new()));
 }
+
+#[test]
+fn parses_typed_values() {
+    let store = parse(&AbsolutePath("/?page=2&sort=name".to_string()));
+    assert_eq!(store.get_as::<u32>("page").ok(), Some(2));
+
+    let (status, err) = store.get_as::<u32>("sort").unwrap_err();
+    assert_eq!(status, StatusCode::BadRequest);
+    assert_eq!((err.name(), err.value()), ("sort", Some("name")));
+
+    let (status, err) = store.get_as::<u32>("limit").unwrap_err();
+    assert_eq!(status, StatusCode::BadRequest);
+    assert_eq!(err.value(), None);
+}
@@ -1,7 +1,18 @@
+use std::any::Any;
+use std::net::IpAddr;
+use std::str::{self, FromStr};
+use mimes::MediaType;
+use param_error::ParamError;
+use proxy;
 use router::RouteResult;
+use server::Server;
+use mount::MountPoint;
 use plugin::{Extensible, Pluggable};
 use typemap::TypeMap;
+use hyper::header::Headers;
+use hyper::mime::Mime;
 use hyper::server::Request as HyperRequest;
+use hyper::status::StatusCode;
 use hyper::uri::RequestUri::AbsolutePath;
 
 /// A container for all the request data.
@@ -17,20 +28,58 @@ pub struct Request<'mw, 'server: 'mw> {
     ///a `HashMap<String, String>` holding all params with names and values
     pub route_result: Option<RouteResult<'mw>>,
 
-    map: TypeMap
+    map: TypeMap,
+
+    server: &'mw Server
 }
 
 impl<'mw, 'server> Request<'mw, 'server> {
-    pub fn from_internal(req: HyperRequest<'mw, 'server>) -> Request<'mw, 'server> {
+    pub fn from_internal(req: HyperRequest<'mw, 'server>,
+                         server: &'mw Server) -> Request<'mw, 'server> {
         Request {
             origin: req,
             route_result: None,
-            map: TypeMap::new()
+            map: TypeMap::new(),
+            server: server
         }
     }
 
+    /// The percent-decoded value of the route parameter `key`, or `None` if
+    /// the route has no such parameter or the request wasn't matched by a
+    /// route.
     pub fn param(&self, key: &str) -> Option<&str> {
-        self.route_result.as_ref().unwrap().param(key)
+        self.route_result.as_ref().and_then(|route_result| route_result.param(key))
+    }
+
+    /// The value of the route parameter `key` without percent-decoding, see
+    /// `RouteResult::raw_param`.
+    pub fn raw_param(&self, key: &str) -> Option<&str> {
+        self.route_result.as_ref().and_then(|route_result| route_result.raw_param(key))
+    }
+
+    /// Parses the route parameter `key` into a `T`.
+    ///
+    /// Fails with `400 Bad Request` if the parameter is missing or can't be
+    /// parsed, which `try_with!` turns into a `NickelError`.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter};
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     server.get("/users/:id", middleware! { |req, res|
+    ///         let id = try_with!(res, req.param_as::<u32>("id"));
+    ///         format!("User {}", id)
+    ///     });
+    /// }
+    /// ```
+    pub fn param_as<T: FromStr>(&self, key: &str) -> Result<T, (StatusCode, ParamError)> {
+        match self.param(key) {
+            Some(value) => value.parse().map_err(|_| ParamError::invalid(key, value).into()),
+            None => Err(ParamError::missing(key).into())
+        }
     }
 
     pub fn path_without_query(&self) -> Option<&str> {
@@ -39,6 +88,216 @@ impl<'mw, 'server> Request<'mw, 'server> {
             _ => None
         }
     }
+
+    /// The path prefix stripped by the `Mount`s the request has passed
+    /// through, or an empty string outside of any `Mount`.
+    pub fn mount_point(&self) -> &str {
+        self.map.get::<MountPoint>().map(|s| &**s).unwrap_or("")
+    }
+
+    /// The application state given to `Nickel::with_data`, or `None` if
+    /// it isn't a `D`.
+    ///
+    /// The type of the state is checked at runtime rather than being a type
+    /// parameter of `Nickel` and `Request`, which would have to be threaded
+    /// through every middleware, router and plugin. Asking for the type
+    /// passed to `with_data` always succeeds.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use nickel::{Nickel, Request, Response, MiddlewareResult, HttpRouter};
+    ///
+    /// struct Counter {
+    ///     visits: AtomicUsize
+    /// }
+    ///
+    /// fn visits<'a>(req: &mut Request, res: Response<'a>) -> MiddlewareResult<'a> {
+    ///     let counter = req.server_data::<Counter>().unwrap();
+    ///     let visits = counter.visits.fetch_add(1, Ordering::Relaxed);
+    ///     res.send(format!("{} visits", visits))
+    /// }
+    ///
+    /// let mut server = Nickel::with_data(Counter { visits: AtomicUsize::new(0) });
+    /// server.get("/", visits);
+    /// ```
+    pub fn server_data<D: Any>(&self) -> Option<&'mw D> {
+        self.server.data().downcast_ref::<D>()
+    }
+
+    /// Whether the request arrived over a TLS connection.
+    pub fn is_secure(&self) -> bool {
+        self.server.is_secure()
+    }
+
+    /// The address of the client which sent the request.
+    ///
+    /// If the connection comes from a proxy trusted with
+    /// `ServerConfig::trust_proxy`, this is the address it forwarded the
+    /// request for, going by the `Forwarded` or `X-Forwarded-For` headers.
+    /// Otherwise it's the address of the connection.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// use nickel::{Nickel, HttpRouter};
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///     server.get("/", middleware! { |req|
+    ///         format!("{} {}://{}", req.client_ip(), req.scheme(), req.host().unwrap_or(""))
+    ///     });
+    /// }
+    /// ```
+    pub fn client_ip(&self) -> IpAddr {
+        self.forwarded().client
+    }
+
+    /// The scheme the client used, `http` or `https`, as forwarded by a
+    /// trusted proxy or otherwise depending on `is_secure`.
+    pub fn scheme(&self) -> &str {
+        match self.forwarded().proto {
+            Some(proto) => proto,
+            None if self.is_secure() => "https",
+            None => "http"
+        }
+    }
+
+    /// The host the client sent the request to, including the port if
+    /// there is one, as forwarded by a trusted proxy or otherwise taken
+    /// from the `Host` header.
+    pub fn host(&self) -> Option<&str> {
+        self.forwarded().host.or_else(|| {
+            self.origin.headers.get_raw("Host")
+                               .and_then(|lines| lines.first())
+                               .and_then(|line| str::from_utf8(line).ok())
+        })
+    }
+
+    fn forwarded(&self) -> proxy::Forwarded {
+        proxy::resolve(&self.origin.headers,
+                       self.origin.remote_addr.ip(),
+                       self.server.trusted_proxies())
+    }
+
+    /// Picks the media type the client prefers out of `offered`, going by
+    /// the quality values and wildcards in its `Accept` header.
+    ///
+    /// Returns `None` if the client accepts none of them, or the first
+    /// offered type if it sent no valid `Accept` header. Types the client likes
+    /// equally are picked in the order they are offered. See `respond_to!`
+    /// for sending a different response per media type.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// use nickel::{Request, Response, MiddlewareResult, MediaType};
+    /// use nickel::status::StatusCode;
+    ///
+    /// # #[allow(dead_code)]
+    /// fn handler<'a>(req: &mut Request, mut res: Response<'a>) -> MiddlewareResult<'a> {
+    ///     res.add_vary("Accept");
+    ///     match req.accepts(&[MediaType::Json, MediaType::Txt]) {
+    ///         Some(MediaType::Json) => res.send(r#"{"greeting": "hello"}"#),
+    ///         Some(_) => res.send("hello"),
+    ///         None => res.error(StatusCode::NotAcceptable, "Only JSON and text are available")
+    ///     }
+    /// }
+    /// ```
+    pub fn accepts(&self, offered: &[MediaType]) -> Option<MediaType> {
+        match accepted_ranges(&self.origin.headers) {
+            Some(accepted) => best_match(&accepted, offered),
+            None => offered.first().cloned()
+        }
+    }
+}
+
+// A media range of an `Accept` header, like `text/*;q=0.5`, with the
+// quality in thousandths.
+struct MediaRange {
+    top: String,
+    sub: String,
+    quality: u16
+}
+
+// Parses the `Accept` header by hand, as hyper's typed header relies on
+// mime 0.1, which fails on `*/*`. A header without any valid range counts
+// as missing.
+fn accepted_ranges(headers: &Headers) -> Option<Vec<MediaRange>> {
+    let line = match headers.get_raw("Accept") {
+        Some(lines) => lines.iter().filter_map(|line| str::from_utf8(line).ok())
+                                   .collect::<Vec<_>>()
+                                   .join(","),
+        None => return None
+    };
+
+    let ranges: Vec<MediaRange> = line.split(',').filter_map(parse_media_range).collect();
+    if ranges.is_empty() {
+        None
+    } else {
+        Some(ranges)
+    }
+}
+
+fn parse_media_range(range: &str) -> Option<MediaRange> {
+    let mut parts = range.split(';');
+    let mut types = parts.next().unwrap().trim().splitn(2, '/');
+    let (top, sub) = match (types.next(), types.next()) {
+        (Some(top), Some(sub)) if !top.is_empty() && !sub.is_empty() => {
+            (top.to_lowercase(), sub.to_lowercase())
+        },
+        _ => return None
+    };
+    if top == "*" && sub != "*" {
+        return None
+    }
+
+    let mut quality = 1000;
+    for param in parts {
+        let mut param = param.splitn(2, '=');
+        if param.next().map(|name| name.trim()) == Some("q") {
+            quality = match param.next().and_then(|q| q.trim().parse::<f32>().ok()) {
+                Some(q) if q >= 0.0 && q <= 1.0 => (q * 1000.0).round() as u16,
+                _ => return None
+            };
+        }
+    }
+
+    Some(MediaRange { top: top, sub: sub, quality: quality })
+}
+
+// Picks the offered type with the highest quality, taking the quality from
+// the most specific matching accepted range.
+fn best_match(accepted: &[MediaRange], offered: &[MediaType]) -> Option<MediaType> {
+    let mut best = None;
+
+    for &media_type in offered {
+        let mime: Mime = media_type.into();
+        let Mime(top, sub, _) = mime;
+        let (top, sub) = (top.to_string().to_lowercase(), sub.to_string().to_lowercase());
+
+        let quality = accepted.iter().filter_map(|range| {
+            let specificity = match (&range.top[..], &range.sub[..]) {
+                ("*", "*") => 0,
+                (t, "*") if t == top => 1,
+                (t, s) if t == top && s == sub => 2,
+                _ => return None
+            };
+            Some((specificity, range.quality))
+        }).max().map(|(_, quality)| quality);
+
+        match (quality, best) {
+            (Some(0), _) | (None, _) => {},
+            (Some(q), Some((_, best_q))) if q <= best_q => {},
+            (Some(q), _) => best = Some((media_type, q))
+        }
+    }
+
+    best.map(|(media_type, _)| media_type)
+}
+
+// The server handling the request, kept out of the public API.
+pub fn server<'a, 'mw, 'server>(req: &'a Request<'mw, 'server>) -> &'mw Server {
+    req.server
 }
 
 impl<'mw, 'server> Extensible for Request<'mw, 'server> {
@@ -52,3 +311,127 @@ impl<'mw, 'server> Extensible for Request<'mw, 'server> {
 }
 
 impl<'mw, 'server> Pluggable for Request<'mw, 'server> {}
+
+#[test]
+fn negotiates_media_types() {
+    let t = |accept: &str, offered: &[MediaType]| {
+        let mut headers = Headers::new();
+        headers.set_raw("Accept", vec![accept.as_bytes().to_vec()]);
+        accepted_ranges(&headers).and_then(|accepted| best_match(&accepted, offered))
+    };
+
+    let offered = [MediaType::Json, MediaType::Html, MediaType::Txt];
+    assert_eq!(t("text/html", &offered), Some(MediaType::Html));
+    assert_eq!(t("text/*", &offered), Some(MediaType::Html));
+    assert_eq!(t("*/*", &offered), Some(MediaType::Json));
+    assert_eq!(t("text/html;q=0.5, application/json;q=0.8", &offered), Some(MediaType::Json));
+    assert_eq!(t("text/*;q=0.9, text/html;q=0.1", &offered), Some(MediaType::Txt));
+    assert_eq!(t("*/*;q=0.1, application/json;q=0", &offered), Some(MediaType::Html));
+    assert_eq!(t("image/png", &offered), None);
+    assert_eq!(t("text/html;level=1;q=0.2, */*;q=0.1", &offered), Some(MediaType::Html));
+    assert_eq!(t("nonsense", &offered), None);
+}
+
+#[test]
+fn responds_with_the_accepted_media_type() {
+    use hyper::header::{ContentType, Vary};
+    use hyper::method::Method;
+    use hyper::status::StatusCode;
+    use middleware::MiddlewareResult;
+    use nickel::Nickel;
+    use response::Response;
+    use router::HttpRouter;
+    use testing::{TestClient, TestRequest};
+
+    fn greet<'a>(req: &mut Request, res: Response<'a>) -> MiddlewareResult<'a> {
+        respond_to!(req, res, {
+            MediaType::Json => r#"{"greeting": "hello"}"#,
+            MediaType::Txt => "hello"
+        })
+    }
+
+    let mut server = Nickel::new();
+    server.get("/", greet);
+    let client = TestClient::new(server);
+    let get = |accept: &str| {
+        client.dispatch(TestRequest::new(Method::Get, "/").raw_header("Accept", accept))
+    };
+
+    let res = get("text/plain");
+    assert_eq!(res.body_str(), Some("hello"));
+    assert_eq!(res.headers().get::<ContentType>(), Some(&ContentType(MediaType::Txt.into())));
+    assert!(res.headers().has::<Vary>());
+
+    assert_eq!(get("*/*").body_str(), Some(r#"{"greeting": "hello"}"#));
+    assert_eq!(get("text/html, */*;q=0.8").body_str(), Some(r#"{"greeting": "hello"}"#));
+    // unparseable headers count as missing
+    assert_eq!(get("").body_str(), Some(r#"{"greeting": "hello"}"#));
+    assert_eq!(get("image/png").status(), StatusCode::NotAcceptable);
+}
+
+#[test]
+fn parses_route_parameters() {
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use testing::TestClient;
+
+    let mut server = Nickel::new();
+    server.get("/users/:id", middleware! { |req, res|
+        let id = try_with!(res, req.param_as::<u32>("id"));
+        format!("{:?} {}", req.param("missing"), id + 1)
+    });
+    server.utilize(middleware! { |req|
+        format!("{:?}", req.param("id"))
+    });
+
+    let client = TestClient::new(server);
+    assert_eq!(client.get("/users/41").body_str(), Some("None 42"));
+    assert_eq!(client.get("/users/john").status(), StatusCode::BadRequest);
+    // no route matched, but looking up a parameter doesn't panic
+    assert_eq!(client.get("/elsewhere").body_str(), Some("None"));
+}
+
+#[test]
+fn trusts_forwarding_headers_from_trusted_proxies() {
+    use hyper::method::Method;
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use server::ServerConfig;
+    use testing::{TestClient, TestRequest};
+
+    let mut server = Nickel::new();
+    server.configure(ServerConfig::new()
+                         .trust_proxy("10.0.0.0/8".parse().unwrap())
+                         .trust_proxy("::1".parse().unwrap()));
+    server.get("/", middleware! { |req|
+        format!("{} {}://{}", req.client_ip(), req.scheme(), req.host().unwrap_or(""))
+    });
+
+    let client = TestClient::new(server);
+    let get = |peer: &str, headers: &[(&str, &str)]| {
+        let mut req = TestRequest::new(Method::Get, "/")
+                          .remote_addr(peer.parse().unwrap())
+                          .raw_header("Host", "internal:8080");
+        for &(name, value) in headers {
+            req = req.raw_header(name, value);
+        }
+        client.dispatch(req).body_str().unwrap().to_string()
+    };
+
+    let forwarded = [("X-Forwarded-For", "203.0.113.7, 10.0.0.2"),
+                     ("X-Forwarded-Proto", "https"),
+                     ("X-Forwarded-Host", "example.com")];
+
+    // an untrusted client can't spoof its address
+    assert_eq!(get("198.51.100.1:4000", &forwarded), "198.51.100.1 http://internal:8080");
+    assert_eq!(get("10.0.0.1:4000", &forwarded), "203.0.113.7 https://example.com");
+
+    // the chain is followed up to the first untrusted address
+    let forwarded = [("X-Forwarded-For", "1.1.1.1, 203.0.113.7, 10.9.9.9")];
+    assert_eq!(get("10.0.0.1:4000", &forwarded), "203.0.113.7 http://internal:8080");
+
+    // `Forwarded` wins over `X-Forwarded-For`
+    let forwarded = [("Forwarded", r#"for=203.0.113.7;proto=https;host="example.com", for="[::1]:9000""#),
+                     ("X-Forwarded-For", "1.1.1.1")];
+    assert_eq!(get("[::1]:4000", &forwarded), "203.0.113.7 https://example.com");
+}
@@ -0,0 +1,166 @@
+use std::io::{self, Read};
+use flate2::read::{GzDecoder, ZlibDecoder, DeflateDecoder};
+use hyper::header::{ContentEncoding, ContentLength, Encoding};
+
+use body::{self, BodyError};
+use middleware::{Middleware, MiddlewareResult, Continue};
+use request::{self, Request};
+use response::Response;
+
+/// Inflates request bodies sent with `Content-Encoding: gzip` or `deflate`,
+/// so that `RawBody` and the parsers built on it see the original body.
+///
+/// The body is read as soon as the middleware runs. Requests with other
+/// encodings get a `415 Unsupported Media Type` response, corrupt bodies a
+/// `400 Bad Request` and bodies which inflate to more than the limit a
+/// `413 Payload Too Large`.
+///
+/// # Examples
+/// ```{rust}
+/// use nickel::{Nickel, RequestDecompressor};
+///
+/// let mut server = Nickel::new();
+/// server.utilize(RequestDecompressor::new().max_size(10 * 1024 * 1024));
+/// ```
+pub struct RequestDecompressor {
+    max_size: Option<u64>
+}
+
+impl RequestDecompressor {
+    /// Creates a middleware limiting inflated bodies to
+    /// `ServerConfig::max_body_size`.
+    pub fn new() -> RequestDecompressor {
+        RequestDecompressor { max_size: None }
+    }
+
+    /// The largest inflated body, in bytes.
+    pub fn max_size(mut self, max: u64) -> RequestDecompressor {
+        self.max_size = Some(max);
+        self
+    }
+
+    fn decompress(&self, req: &mut Request, encodings: &[Encoding]) -> Result<Vec<u8>, BodyError> {
+        let limit = self.max_size.unwrap_or(request::server(req).max_body_size());
+
+        // encodings are listed in the order they were applied
+        let mut body = try!(body::read(req));
+        for encoding in encodings.iter().rev() {
+            if body.is_empty() {
+                break
+            }
+            body = try!(inflate(encoding, body, limit));
+        }
+
+        Ok(body)
+    }
+}
+
+impl Middleware for RequestDecompressor {
+    fn invoke<'mw, 'conn>(&'mw self, req: &mut Request<'mw, 'conn>, res: Response<'mw>)
+                          -> MiddlewareResult<'mw> {
+        let encodings = match req.origin.headers.get::<ContentEncoding>() {
+            Some(&ContentEncoding(ref encodings)) => encodings.clone(),
+            None => return Ok(Continue(res))
+        };
+
+        match self.decompress(req, &encodings) {
+            Ok(body) => {
+                req.origin.headers.remove::<ContentEncoding>();
+                req.origin.headers.set(ContentLength(body.len() as u64));
+                body::replace(req, body);
+                Ok(Continue(res))
+            },
+            Err(err) => res.error(err.status(), err.to_string())
+        }
+    }
+}
+
+fn inflate(encoding: &Encoding, body: Vec<u8>, limit: u64) -> Result<Vec<u8>, BodyError> {
+    // Read one byte past the limit to tell a body of exactly `limit` bytes
+    // from a larger one.
+    let max = limit.saturating_add(1);
+    let mut inflated = vec![];
+    let result = match *encoding {
+        Encoding::Identity => return Ok(body),
+        Encoding::Gzip => gunzip(&body, max, &mut inflated),
+        Encoding::EncodingExt(ref ext) if ext == "x-gzip" => gunzip(&body, max, &mut inflated),
+        // `deflate` is meant to be zlib wrapped, but some clients send raw
+        // deflate data
+        Encoding::Deflate if is_zlib(&body) => {
+            ZlibDecoder::new(&body[..]).take(max).read_to_end(&mut inflated)
+        },
+        Encoding::Deflate => DeflateDecoder::new(&body[..]).take(max).read_to_end(&mut inflated),
+        _ => return Err(BodyError::UnsupportedMediaType)
+    };
+
+    if result.is_err() {
+        return Err(BodyError::Malformed(format!("The {} request body is corrupt", encoding)))
+    }
+
+    if inflated.len() as u64 > limit {
+        return Err(BodyError::TooLarge(limit))
+    }
+
+    Ok(inflated)
+}
+
+fn gunzip(body: &[u8], max: u64, inflated: &mut Vec<u8>) -> io::Result<usize> {
+    GzDecoder::new(body).and_then(|decoder| decoder.take(max).read_to_end(inflated))
+}
+
+// A zlib header is a deflate method byte and a check byte making the pair
+// a multiple of 31.
+fn is_zlib(body: &[u8]) -> bool {
+    body.len() >= 2 && body[0] & 0x0f == 8 && ((body[0] as u16) << 8 | body[1] as u16) % 31 == 0
+}
+
+#[test]
+fn inflates_request_bodies() {
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder, DeflateEncoder};
+    use hyper::method::Method;
+    use hyper::status::StatusCode;
+    use body::RawBody;
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use testing::{TestClient, TestRequest};
+
+    let mut server = Nickel::new();
+    server.utilize(RequestDecompressor::new().max_size(16));
+    server.post("/", middleware! { |req, res|
+        let body = try_with!(res, req.raw_body());
+        String::from_utf8_lossy(body).into_owned()
+    });
+
+    let client = TestClient::new(server);
+    let post = |encoding: Encoding, body: Vec<u8>| {
+        client.dispatch(TestRequest::new(Method::Post, "/")
+                            .header(ContentEncoding(vec![encoding]))
+                            .body(body))
+    };
+
+    let mut gzip = GzEncoder::new(vec![], Compression::Default);
+    gzip.write_all(b"hello gzip").unwrap();
+    assert_eq!(post(Encoding::Gzip, gzip.finish().unwrap()).body_str(), Some("hello gzip"));
+
+    let mut zlib = ZlibEncoder::new(vec![], Compression::Default);
+    zlib.write_all(b"hello zlib").unwrap();
+    assert_eq!(post(Encoding::Deflate, zlib.finish().unwrap()).body_str(), Some("hello zlib"));
+
+    let mut deflate = DeflateEncoder::new(vec![], Compression::Default);
+    deflate.write_all(b"hello deflate").unwrap();
+    assert_eq!(post(Encoding::Deflate, deflate.finish().unwrap()).body_str(), Some("hello deflate"));
+
+    // a small body inflating past the limit
+    let mut bomb = GzEncoder::new(vec![], Compression::Best);
+    bomb.write_all(&[0; 1024]).unwrap();
+    let res = post(Encoding::Gzip, bomb.finish().unwrap());
+    assert_eq!(res.status(), StatusCode::PayloadTooLarge);
+
+    let res = post(Encoding::Gzip, b"not gzip".to_vec());
+    assert_eq!(res.status(), StatusCode::BadRequest);
+
+    let res = post(Encoding::Compress, b"hello".to_vec());
+    assert_eq!(res.status(), StatusCode::UnsupportedMediaType);
+}
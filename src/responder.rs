@@ -68,6 +68,8 @@ dual_impl!(&'a [u8],
            Vec<u8>,
             |self, res| {
                 maybe_set_type(&mut res, MediaType::Bin);
+                let length = self.len() as u64;
+                res.set_header_fallback(|| header::ContentLength(length));
 
                 let mut stream = try!(res.start());
                 match stream.write_all(&self[..]) {
@@ -3,11 +3,13 @@ use std::sync::RwLock;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use serialize::Encodable;
 use hyper::status::StatusCode;
 use hyper::server::Response as HyperResponse;
 use hyper::header::{
-    Headers, Date, HttpDate, Server, ContentType, ContentLength, Header, HeaderFormat
+    Headers, Date, HttpDate, Server, ContentType, ContentLength, LastModified, SetCookie, Vary, Header,
+    HeaderFormat
 };
 use hyper::net::{Fresh, Streaming};
 use time;
@@ -17,25 +19,46 @@ use mustache::Template;
 use std::io::{self, Read, Write, copy};
 use std::fs::File;
 use std::any::Any;
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+use std::{mem, thread};
+use cookie::{Cookie, CookieJar};
+use cookies::CookieBuilder;
+use server::Server as NickelServer;
+use compression::Encoder;
+use byte_range::{self, RangeRequest};
 use {NickelError, Halt, MiddlewareResult, Responder};
 use modifier::Modifier;
 
 pub type TemplateCache = RwLock<HashMap<String, Template>>;
 
+type SendHook<'a> = Box<FnMut(&mut Response<'a, Fresh>) + 'a>;
+
+/// Where a response goes when the handler holding it panics, see
+/// `MiddlewareStack::invoke`.
+pub type Rescue<'a, T = Fresh> = Rc<RefCell<Option<HyperResponse<'a, T>>>>;
+
 ///A container for the response
 pub struct Response<'a, T: 'static + Any = Fresh> {
     ///the original `hyper::server::Response`
-    origin: HyperResponse<'a, T>,
-    templates: &'a TemplateCache
+    origin: Origin<'a, T>,
+    server: &'a NickelServer,
+    on_send: Vec<SendHook<'a>>,
+    encoder: Option<Encoder>,
+    range: Option<RangeRequest>
 }
 
 impl<'a> Response<'a, Fresh> {
     pub fn from_internal<'c, 'd>(response: HyperResponse<'c, Fresh>,
-                                 templates: &'c TemplateCache)
+                                 server: &'c NickelServer)
                                 -> Response<'c, Fresh> {
         Response {
-            origin: response,
-            templates: templates
+            origin: Origin::new(response),
+            server: server,
+            on_send: vec![],
+            encoder: None,
+            range: None
         }
     }
 
@@ -86,6 +109,87 @@ impl<'a> Response<'a, Fresh> {
         self
     }
 
+    /// Adds a `Set-Cookie` header, keeping the cookies set before.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// use std::time::Duration;
+    /// use nickel::{Request, Response, MiddlewareResult, CookieBuilder, SameSite};
+    ///
+    /// # #[allow(dead_code)]
+    /// fn handler<'a>(_: &mut Request, mut res: Response<'a>) -> MiddlewareResult<'a> {
+    ///     res.set_cookie(CookieBuilder::new("theme", "dark")
+    ///                        .path("/")
+    ///                        .max_age(Duration::from_secs(30 * 24 * 60 * 60))
+    ///                        .http_only(true)
+    ///                        .same_site(SameSite::Lax));
+    ///     res.send("remembered")
+    /// }
+    /// ```
+    pub fn set_cookie<C: Into<Cookie>>(&mut self, cookie: C) -> &mut Response<'a> {
+        let mut cookies = self.headers().get::<SetCookie>()
+                                        .map(|cookies| cookies.0.clone())
+                                        .unwrap_or(vec![]);
+        cookies.push(cookie.into());
+        self.set(SetCookie(cookies))
+    }
+
+    /// Adds a `Set-Cookie` header with the value signed by the key given to
+    /// `Nickel::secret_key`, so that changes made by the client can be
+    /// detected with `RequestCookies::get_signed`.
+    pub fn set_signed_cookie<C: Into<Cookie>>(&mut self, cookie: C) -> &mut Response<'a> {
+        let jar = CookieJar::new(self.server.secret_key());
+        jar.signed().add(cookie.into());
+        self.set_jar_cookies(jar)
+    }
+
+    /// Adds a `Set-Cookie` header with the value encrypted by the key given
+    /// to `Nickel::secret_key`, to be read with
+    /// `RequestCookies::get_encrypted`.
+    pub fn set_encrypted_cookie<C: Into<Cookie>>(&mut self, cookie: C) -> &mut Response<'a> {
+        let jar = CookieJar::new(self.server.secret_key());
+        jar.encrypted().add(cookie.into());
+        self.set_jar_cookies(jar)
+    }
+
+    /// Tells the client to delete the cookie called `name` with the
+    /// path `/`.
+    ///
+    /// A cookie set with a different `Path` or a `Domain` can be deleted by
+    /// setting it again with those attributes and a `max_age` of zero.
+    pub fn remove_cookie(&mut self, name: &str) -> &mut Response<'a> {
+        self.set_cookie(CookieBuilder::new(name, "")
+                            .path("/")
+                            .max_age(Duration::from_secs(0))
+                            .expires(time::at_utc(time::Timespec::new(0, 0))))
+    }
+
+    fn set_jar_cookies(&mut self, jar: CookieJar) -> &mut Response<'a> {
+        for cookie in jar.delta() {
+            self.set_cookie(cookie);
+        }
+        self
+    }
+
+    /// Adds `header` to the `Vary` header, telling caches that the response
+    /// depends on the value of that request header.
+    pub fn add_vary(&mut self, header: &str) -> &mut Response<'a> {
+        let vary = match self.headers().get::<Vary>() {
+            Some(&Vary::Any) => return self,
+            Some(&Vary::Items(ref items)) => {
+                if items.iter().any(|item| item.eq_ignore_ascii_case(header)) {
+                    return self
+                }
+                let mut items = items.clone();
+                items.push(header.parse().unwrap());
+                items
+            },
+            None => vec![header.parse().unwrap()]
+        };
+
+        self.set(Vary::Items(vary))
+    }
+
     /// Writes a response
     ///
     /// # Examples
@@ -104,6 +208,11 @@ impl<'a> Response<'a, Fresh> {
 
     /// Writes a file to the output.
     ///
+    /// `Range` requests for parts of the file are answered with a
+    /// `206 Partial Content` response, or a `416 Range Not Satisfiable` one
+    /// if none of the parts is inside the file. Ranges are ignored if the
+    /// status was set to anything but `200 OK`.
+    ///
     /// # Examples
     /// ```{rust}
     /// use nickel::{Request, Response, MiddlewareResult};
@@ -117,8 +226,6 @@ impl<'a> Response<'a, Fresh> {
     /// ```
     pub fn send_file<P:AsRef<Path>>(mut self, path: P) -> MiddlewareResult<'a> {
         let path = path.as_ref();
-        // Chunk the response
-        self.origin.headers_mut().remove::<ContentLength>();
         // Determine content type by file extension or default to binary
         let mime = mime_from_filename(path).unwrap_or(MediaType::Bin);
         self.set(mime);
@@ -127,6 +234,30 @@ impl<'a> Response<'a, Fresh> {
             File::open(path).map_err(|e| format!("Failed to send file '{:?}': {}",
                                                  path, e))
         });
+        let metadata = try_with!(self, {
+            file.metadata().map_err(|e| format!("Failed to send file '{:?}': {}",
+                                                path, e))
+        });
+
+        let length = metadata.len();
+        let last_modified = metadata.modified().ok().and_then(http_date);
+        if let Some(date) = last_modified {
+            self.set_header_fallback(|| LastModified(date));
+        }
+        // Lets middleware like `ResponseCompressor` see the size up front
+        self.origin.headers_mut().set(ContentLength(length));
+
+        // Ranges only apply to the file itself, not to e.g. a 404 page
+        let range = self.range.take();
+        let ranges = if self.status() == StatusCode::Ok {
+            self.origin.headers_mut().set_raw("Accept-Ranges", vec![b"bytes".to_vec()]);
+            range.and_then(|range| range.resolve(length, last_modified.as_ref()))
+        } else {
+            None
+        };
+        if let Some(ranges) = ranges {
+            return byte_range::send(self, file, length, ranges)
+        }
 
         let mut stream = try!(self.start());
         match copy(&mut file, &mut stream) {
@@ -213,12 +344,12 @@ impl<'a> Response<'a, Fresh> {
         }
 
         // Fast path doesn't need writer lock
-        if let Some(t) = self.templates.read().unwrap().get(path.as_ref()) {
+        if let Some(t) = self.server.templates().read().unwrap().get(path.as_ref()) {
             return render(self, t, data);
         }
 
         // We didn't find the template, get writers lock
-        let mut templates = self.templates.write().unwrap();
+        let mut templates = self.server.templates().write().unwrap();
 
         // Additional clone required for now as the entry api doesn't give us a key ref
         let path = path.into();
@@ -239,12 +370,57 @@ impl<'a> Response<'a, Fresh> {
         render(self, template, data)
     }
 
+    /// Registers a function which gets to inspect and modify the status and
+    /// headers right before they are sent. This also happens when the
+    /// request ends in an error, before the error handlers write the body.
+    ///
+    /// Functions registered later run first, so the ones registered by
+    /// middleware early in the stack see the changes made further down.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// extern crate time;
+    ///
+    /// use nickel::Nickel;
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///
+    ///     // log each request along with its status and handling time
+    ///     server.utilize(middleware! { |req, mut res|
+    ///         let uri = req.origin.uri.to_string();
+    ///         let start = time::precise_time_ns();
+    ///
+    ///         res.on_send(move |res| {
+    ///             let elapsed = (time::precise_time_ns() - start) / 1000;
+    ///             println!("{} {} {}us", uri, res.status(), elapsed);
+    ///         });
+    ///     });
+    /// }
+    /// ```
+    pub fn on_send<F>(&mut self, f: F)
+            where F: FnMut(&mut Response<'a, Fresh>) + 'a {
+        self.on_send.push(Box::new(f))
+    }
+
     pub fn start(mut self) -> Result<Response<'a, Streaming>, NickelError<'a>> {
         self.set_fallback_headers();
 
-        let Response { origin, templates } = self;
-        match origin.start() {
-            Ok(origin) => Ok(Response { origin: origin, templates: templates }),
+        let mut hooks = mem::replace(&mut self.on_send, vec![]);
+        for hook in hooks.iter_mut().rev() {
+            hook(&mut self);
+        }
+
+        let Response { origin, server, encoder, .. } = self;
+        match origin.into_inner().start() {
+            Ok(origin) => Ok(Response {
+                origin: Origin::new(origin),
+                server: server,
+                on_send: vec![],
+                encoder: encoder,
+                range: None
+            }),
             Err(e) =>
                 unsafe {
                     Err(NickelError::without_response(format!("Failed to start response: {}", e)))
@@ -256,11 +432,17 @@ impl<'a> Response<'a, Fresh> {
 impl<'a, 'b> Write for Response<'a, Streaming> {
     #[inline(always)]
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.origin.write(buf)
+        match self.encoder {
+            Some(ref mut encoder) => encoder.write(buf, &mut *self.origin),
+            None => self.origin.write(buf)
+        }
     }
 
     #[inline(always)]
     fn flush(&mut self) -> io::Result<()> {
+        if let Some(ref mut encoder) = self.encoder {
+            try!(encoder.flush(&mut *self.origin));
+        }
         self.origin.flush()
     }
 }
@@ -278,7 +460,12 @@ impl<'a, 'b> Response<'a, Streaming> {
 
     /// Flushes all writing of a response to the client.
     pub fn end(self) -> io::Result<()> {
-        self.origin.end()
+        let Response { origin, encoder, .. } = self;
+        let mut origin = origin.into_inner();
+        if let Some(encoder) = encoder {
+            try!(encoder.finish(&mut origin));
+        }
+        origin.end()
     }
 }
 
@@ -294,6 +481,74 @@ impl <'a, T: 'static + Any> Response<'a, T> {
     }
 }
 
+// Owns the hyper response. hyper sends unstarted responses when they're
+// dropped, so if a handler panics while holding one it is handed over to
+// `rescue` instead, letting the error handlers answer.
+struct Origin<'a, T: 'static + Any> {
+    response: Option<HyperResponse<'a, T>>,
+    rescue: Option<Rescue<'a, T>>
+}
+
+impl<'a, T: 'static + Any> Origin<'a, T> {
+    fn new(response: HyperResponse<'a, T>) -> Origin<'a, T> {
+        Origin {
+            response: Some(response),
+            rescue: None
+        }
+    }
+
+    fn into_inner(mut self) -> HyperResponse<'a, T> {
+        self.response.take().unwrap()
+    }
+}
+
+impl<'a, T: 'static + Any> Deref for Origin<'a, T> {
+    type Target = HyperResponse<'a, T>;
+
+    fn deref(&self) -> &HyperResponse<'a, T> {
+        self.response.as_ref().unwrap()
+    }
+}
+
+impl<'a, T: 'static + Any> DerefMut for Origin<'a, T> {
+    fn deref_mut(&mut self) -> &mut HyperResponse<'a, T> {
+        self.response.as_mut().unwrap()
+    }
+}
+
+impl<'a, T: 'static + Any> Drop for Origin<'a, T> {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return
+        }
+
+        if let (Some(rescue), Some(response)) = (self.rescue.take(), self.response.take()) {
+            *rescue.borrow_mut() = Some(response);
+        }
+    }
+}
+
+// Compresses the body of `res` once it's started, see `ResponseCompressor`.
+pub fn set_encoder(res: &mut Response, encoder: Encoder) {
+    res.encoder = Some(encoder);
+}
+
+// Hands `res` over to `rescue` if the handler holding it panics.
+pub fn set_rescue<'a>(res: &mut Response<'a>, rescue: Rescue<'a>) {
+    res.origin.rescue = Some(rescue);
+}
+
+// The parts of a file `send_file` should send.
+pub fn set_range(res: &mut Response, range: Option<RangeRequest>) {
+    res.range = range;
+}
+
+fn http_date(time: SystemTime) -> Option<HttpDate> {
+    time.duration_since(UNIX_EPOCH).ok().map(|since| {
+        HttpDate(time::at_utc(time::Timespec::new(since.as_secs() as i64, 0)))
+    })
+}
+
 fn mime_from_filename<P: AsRef<Path>>(path: P) -> Option<MediaType> {
     path.as_ref()
         .extension()
@@ -302,6 +557,41 @@ fn mime_from_filename<P: AsRef<Path>>(path: P) -> Option<MediaType> {
         .and_then(|s| s.parse().ok())
 }
 
+#[test]
+fn runs_send_hooks_before_sending() {
+    use {Nickel, HttpRouter};
+    use hyper::header::ContentLanguage;
+    use hyper::status::StatusCode::{Ok, NotFound};
+    use testing::TestClient;
+
+    let mut server = Nickel::new();
+    server.utilize(middleware! { |_, mut res|
+        res.on_send(|res| {
+            let status = res.status();
+            res.headers_mut().set_raw("X-Status", vec![status.to_string().into_bytes()]);
+        });
+    });
+    server.utilize(middleware! { |_, mut res|
+        res.on_send(|res| if res.status() == NotFound { res.set(Ok); });
+    });
+    server.get("/", middleware! { |_, mut res|
+        res.on_send(|res| { res.set(ContentLanguage(vec![])); });
+        "hello"
+    });
+
+    let client = TestClient::new(server);
+
+    let res = client.get("/");
+    assert_eq!(res.body_str(), Some("hello"));
+    assert!(res.headers().has::<ContentLanguage>());
+    assert_eq!(res.headers().get_raw("X-Status").unwrap(), &[b"200 OK".to_vec()]);
+
+    // hooks also run for errors, in reverse order of registration
+    let res = client.get("/missing");
+    assert_eq!(res.status(), Ok);
+    assert_eq!(res.headers().get_raw("X-Status").unwrap(), &[b"200 OK".to_vec()]);
+}
+
 #[test]
 fn matches_content_type () {
     assert_eq!(Some(MediaType::Txt), mime_from_filename("test.txt"));
@@ -1,5 +1,6 @@
 use super::Matcher;
 use regex::{Regex, Captures};
+use mimes::EXTENSIONS;
 
 impl From<Regex> for Matcher {
     fn from(regex: Regex) -> Matcher {
@@ -16,28 +17,41 @@ impl<'a> From<&'a str> for Matcher {
 
 lazy_static! {
     static ref REGEX_VAR_SEQ: Regex = Regex::new(r":([,a-zA-Z0-9_-]*)").unwrap();
+    // only known extensions are split off as the format, so `v1.2.3` or
+    // `user@example.com` stay whole. Routes ending in `.:format` take any.
+    static ref FORMAT_SEQ: String = format!("(\\.(?P<{}>{}))?", FORMAT_PARAM, EXTENSIONS.join("|"));
 }
 
 pub static FORMAT_PARAM:      &'static str = "format";
 // FIXME: Once const fn lands this could be defined in terms of the above
 static FORMAT_VAR:            &'static str = ":format";
-static VAR_SEQ:               &'static str = "[,a-zA-Z0-9_-]*";
-static VAR_SEQ_WITH_SLASH:    &'static str = "[,/a-zA-Z0-9_-]*";
+// Any RFC 3986 segment character (unreserved, percent-encoded, sub-delims,
+// ':' and '@') as well as non-ASCII ones. The ':' is escaped so it isn't
+// taken for a variable.
+static VAR_SEQ:               &'static str = r"[a-zA-Z0-9._~%!$&'()*+,;=\x3A@\x{80}-\x{10FFFF}-]*";
+static VAR_SEQ_WITH_SLASH:    &'static str = r"[/a-zA-Z0-9._~%!$&'()*+,;=\x3A@\x{80}-\x{10FFFF}-]*";
+// A variable ending a route without `:format` stops as early as it can, so
+// that a known extension is left for the implicit format.
+static VAR_SEQ_LAZY:          &'static str = r"[a-zA-Z0-9._~%!$&'()*+,;=\x3A@\x{80}-\x{10FFFF}-]*?";
+// An explicit `:format` never contains a dot, so `/:id.:format` splits at
+// the last one.
+static FORMAT_VAR_SEQ:        &'static str = r"[a-zA-Z0-9_~%!$&'()*+,;=\x3A@\x{80}-\x{10FFFF}-]*";
 // matches request params (e.g. ?foo=true&bar=false)
 static REGEX_PARAM_SEQ:       &'static str = "(\\?[a-zA-Z0-9%_=&-]*)?";
 
 impl From<String> for Matcher {
     fn from(s: String) -> Matcher {
-        let with_format = if s.contains(FORMAT_VAR) {
-            s
-        } else {
-            format!("{}(\\.{})?", s, FORMAT_VAR)
-        };
+        let implicit_format = !s.contains(FORMAT_VAR);
+        let format_seq = if implicit_format { &FORMAT_SEQ[..] } else { "" };
+
+        // Dots in the route, as in `/:id.:format` or `/favicon.ico`, only
+        // match a dot
+        let escaped = escape_dots(&s);
 
         // First mark all double wildcards for replacement. We can't directly
         // replace them since the replacement does contain the * symbol as well,
         // which would get overwritten with the next replace call
-        let with_placeholder = with_format.replace("**", "___DOUBLE_WILDCARD___");
+        let with_placeholder = escaped.replace("**", "___DOUBLE_WILDCARD___");
 
         // Then replace the regular wildcard symbols (*) with the appropriate regex
         let star_replaced = with_placeholder.replace("*", VAR_SEQ);
@@ -48,12 +62,39 @@ impl From<String> for Matcher {
         // Add a named capture for each :(variable) symbol
         let named_captures = REGEX_VAR_SEQ.replace_all(&wildcarded, |captures: &Captures| {
             // There should only ever be one match (after subgroup 0)
-            let c = captures.iter().skip(1).next().unwrap();
-            format!("(?P<{}>[,a-zA-Z0-9%_-]*)", c.unwrap())
+            let name = captures.iter().skip(1).next().unwrap().unwrap();
+            let ends_route = captures.pos(0).map(|(_, end)| end) == Some(wildcarded.len());
+            let seq = if name == FORMAT_PARAM {
+                FORMAT_VAR_SEQ
+            } else if implicit_format && ends_route {
+                VAR_SEQ_LAZY
+            } else {
+                VAR_SEQ
+            };
+            format!("(?P<{}>{})", name, seq)
         });
 
-        let line_regex = format!("^{}{}$", named_captures, REGEX_PARAM_SEQ);
+        let line_regex = format!("^{}{}{}$", named_captures, format_seq, REGEX_PARAM_SEQ);
         let regex = Regex::new(&line_regex).unwrap();
+
+        let with_format = if format_seq.is_empty() {
+            s
+        } else {
+            format!("{}(\\.{})?", s, FORMAT_VAR)
+        };
         Matcher::new(with_format, regex)
     }
 }
+
+fn escape_dots(route: &str) -> String {
+    let mut escaped = String::with_capacity(route.len());
+    let mut previous = None;
+    for c in route.chars() {
+        if c == '.' && previous != Some('\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        previous = Some(c);
+    }
+    escaped
+}
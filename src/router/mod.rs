@@ -1,6 +1,6 @@
 //! A `Router` assigns `Middleware` to paths and resolves them per request
 pub use self::http_router::HttpRouter;
-pub use self::router::{Router, Route, RouteResult};
+pub use self::router::{Router, Route, RouteGroup, RouteResult};
 pub use self::matcher::Matcher;
 pub use self::into_matcher::FORMAT_PARAM;
 
@@ -1,4 +1,5 @@
-use middleware::{Middleware, Continue, MiddlewareResult};
+use std::borrow::Cow;
+use middleware::{Middleware, Continue, Halt, MiddlewareResult};
 
 use request::Request;
 use response::Response;
@@ -13,7 +14,8 @@ use router::{Matcher, FORMAT_PARAM};
 pub struct Route {
     pub method: Method,
     pub handler: Box<Middleware + Send + Sync + 'static>,
-    matcher: Matcher
+    matcher: Matcher,
+    group: Option<usize>
 }
 
 /// A RouteResult is what the router returns when `match_route` is called.
@@ -22,23 +24,39 @@ pub struct Route {
 /// evaluated string
 pub struct RouteResult<'mw> {
     pub route: &'mw Route,
-    params: Vec<(String, String)>
+    params: Vec<Param>
+}
+
+struct Param {
+    name: String,
+    raw: String,
+    decoded: String
 }
 
 impl<'mw> RouteResult<'mw> {
+    /// The value of the parameter `key`, with percent-encoded characters
+    /// decoded. Invalid UTF-8 is replaced by `U+FFFD`.
     pub fn param(&self, key: &str) -> Option<&str> {
-        for &(ref k, ref v) in &self.params {
-            if k == &key {
-                return Some(&v[..])
-            }
-        }
+        self.find_param(key).map(|param| &param.decoded[..]).or_else(|| default_param(key))
+    }
 
-        // FIXME: should have a default format
-        if key == FORMAT_PARAM {
-            Some("")
-        } else {
-            None
-        }
+    /// The value of the parameter `key` without percent-decoding, apart
+    /// from the unreserved characters decoded by `Router::match_route`.
+    pub fn raw_param(&self, key: &str) -> Option<&str> {
+        self.find_param(key).map(|param| &param.raw[..]).or_else(|| default_param(key))
+    }
+
+    fn find_param(&self, key: &str) -> Option<&Param> {
+        self.params.iter().find(|param| param.name == key)
+    }
+}
+
+fn default_param(key: &str) -> Option<&'static str> {
+    // FIXME: should have a default format
+    if key == FORMAT_PARAM {
+        Some("")
+    } else {
+        None
     }
 }
 
@@ -47,34 +65,125 @@ impl<'mw> RouteResult<'mw> {
 /// added to the middleware stack with `server.utilize(router)`.
 pub struct Router {
     routes: Vec<Route>,
+    middleware: Vec<Box<Middleware + Send + Sync>>,
+    groups: Vec<Vec<Box<Middleware + Send + Sync>>>
+}
+
+/// Routes of a `Router` sharing their own middleware, see `Router::group`.
+pub struct RouteGroup<'a> {
+    router: &'a mut Router,
+    index: usize
 }
 
 impl Router {
     pub fn new () -> Router {
         Router {
-            routes: Vec::new()
+            routes: Vec::new(),
+            middleware: Vec::new(),
+            groups: Vec::new()
         }
     }
 
+    /// Registers a middleware handler which only runs for requests matching
+    /// one of this router's routes. It runs after the route has been
+    /// resolved, so `Request::param` is available, and before the route's
+    /// handler. Returning `Halt` or an error skips the route's handler.
+    ///
+    /// To give only some of the routes their own middleware, see `group`.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// extern crate hyper;
+    ///
+    /// use nickel::{Nickel, HttpRouter};
+    /// use nickel::status::StatusCode;
+    /// use hyper::header::Authorization;
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///
+    ///     let mut admin = Nickel::router();
+    ///     admin.utilize(middleware! { |req, res|
+    ///         if !req.origin.headers.has::<Authorization<String>>() {
+    ///             return res.error(StatusCode::Unauthorized, "Login required")
+    ///         }
+    ///     });
+    ///     admin.get("/admin/users", middleware!("Only for admins"));
+    ///
+    ///     server.utilize(admin);
+    /// }
+    /// ```
+    pub fn utilize<T: Middleware>(&mut self, handler: T) {
+        self.middleware.push(Box::new(handler));
+    }
+
+    /// Adds a group of routes with middleware of their own, which runs
+    /// after the router's middleware for requests matching one of the
+    /// group's routes.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// #[macro_use] extern crate nickel;
+    /// extern crate hyper;
+    ///
+    /// use nickel::{Nickel, HttpRouter};
+    /// use nickel::status::StatusCode;
+    /// use hyper::header::Authorization;
+    ///
+    /// fn main() {
+    ///     let mut server = Nickel::new();
+    ///
+    ///     let mut router = Nickel::router();
+    ///     router.get("/", middleware!("For everyone"));
+    ///     router.group(|admin| {
+    ///         admin.utilize(middleware! { |req, res|
+    ///             if !req.origin.headers.has::<Authorization<String>>() {
+    ///                 return res.error(StatusCode::Unauthorized, "Login required")
+    ///             }
+    ///         });
+    ///         admin.get("/admin/users", middleware!("Only for admins"));
+    ///     });
+    ///
+    ///     server.utilize(router);
+    /// }
+    /// ```
+    pub fn group<F: FnOnce(&mut RouteGroup)>(&mut self, define: F) {
+        self.groups.push(Vec::new());
+        let index = self.groups.len() - 1;
+        define(&mut RouteGroup { router: self, index: index });
+    }
+
+    /// Finds the route for `path`.
+    ///
+    /// Routes are matched against the path as sent by the client, so an
+    /// encoded `/` (`%2F`) stays part of its segment, and the parameters are
+    /// decoded afterwards. Encoded unreserved characters like `%7E` for `~`
+    /// are decoded before matching.
     pub fn match_route<'mw>(&'mw self, method: &Method, path: &str) -> Option<RouteResult<'mw>> {
+        let path = normalize_path(path);
         self.routes
             .iter()
-            .find(|item| item.method == *method && item.matcher.is_match(path))
+            .find(|item| item.method == *method && item.matcher.is_match(&path))
             .map(|route|
                 RouteResult {
-                    params: extract_params(route, path),
+                    params: extract_params(route, &path),
                     route: route
                 }
             )
     }
 }
 
-fn extract_params(route: &Route, path: &str) -> Vec<(String, String)> {
+fn extract_params(route: &Route, path: &str) -> Vec<Param> {
     match route.matcher.captures(path) {
         Some(captures) => {
             captures.iter_named()
                     .filter_map(|(name, subcap)| {
-                        subcap.map(|cap| (name.to_string(), cap.to_string()))
+                        subcap.map(|cap| Param {
+                            name: name.to_string(),
+                            raw: cap.to_string(),
+                            decoded: percent_decode(cap, |_| true)
+                        })
                     })
                     .collect()
         }
@@ -82,12 +191,55 @@ fn extract_params(route: &Route, path: &str) -> Vec<(String, String)> {
     }
 }
 
+// Decodes the escaped unreserved characters (RFC 3986, section 6.2.2.2), as
+// `/%7Euser` and `/~user` are the same path.
+fn normalize_path(path: &str) -> Cow<str> {
+    if !path.contains('%') {
+        return Cow::Borrowed(path)
+    }
+
+    Cow::Owned(percent_decode(path, |b| {
+        (b < 0x80 && (b as char).is_alphanumeric()) || b"-._~".contains(&b)
+    }))
+}
+
+// Decodes the `%XX` escapes of the bytes `decode` accepts, leaving invalid
+// escapes as they are.
+fn percent_decode<F: Fn(u8) -> bool>(input: &str, decode: F) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                let byte = hi * 16 + lo;
+                if decode(byte) {
+                    decoded.push(byte);
+                    i += 3;
+                    continue
+                }
+            }
+        }
+
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|value| value as u8)
+}
+
 impl HttpRouter for Router {
     fn add_route<M: Into<Matcher>, H: Middleware>(&mut self, method: Method, matcher: M, handler: H) -> &mut Self {
         let route = Route {
             matcher: matcher.into(),
             method: method,
             handler: Box::new(handler),
+            group: None
         };
 
         self.routes.push(route);
@@ -95,6 +247,28 @@ impl HttpRouter for Router {
     }
 }
 
+impl<'a> RouteGroup<'a> {
+    /// Registers a middleware handler which only runs for requests matching
+    /// one of this group's routes, see `Router::utilize`.
+    pub fn utilize<T: Middleware>(&mut self, handler: T) {
+        self.router.groups[self.index].push(Box::new(handler));
+    }
+}
+
+impl<'a> HttpRouter for RouteGroup<'a> {
+    fn add_route<M: Into<Matcher>, H: Middleware>(&mut self, method: Method, matcher: M, handler: H) -> &mut Self {
+        let route = Route {
+            matcher: matcher.into(),
+            method: method,
+            handler: Box::new(handler),
+            group: Some(self.index)
+        };
+
+        self.router.routes.push(route);
+        self
+    }
+}
+
 impl Middleware for Router {
     fn invoke<'mw, 'conn>(&'mw self, req: &mut Request<'mw, 'conn>, mut res: Response<'mw>)
                           -> MiddlewareResult<'mw> {
@@ -110,7 +284,16 @@ impl Middleware for Router {
             Some(route_result) => {
                 res.set(StatusCode::Ok);
                 let handler = &route_result.route.handler;
+                let group = route_result.route.group.map_or(&[][..], |index| &self.groups[index][..]);
                 req.route_result = Some(route_result);
+
+                for middleware in self.middleware.iter().chain(group.iter()) {
+                    res = match try!(middleware.invoke(req, res)) {
+                        Continue(res) => res,
+                        Halt(res) => return Ok(Halt(res))
+                    };
+                }
+
                 handler.invoke(req, res)
             },
             None => Ok(Continue(res))
@@ -218,14 +401,15 @@ fn can_match_var_routes () {
     assert!(route_result.is_some());
 
     let route_result = route_result.unwrap();
-    assert_eq!(route_result.param("userid"), Some("John%20Doe"));
+    assert_eq!(route_result.param("userid"), Some("John Doe"));
+    assert_eq!(route_result.raw_param("userid"), Some("John%20Doe"));
 
     // check for optional format param
     let route_result = route_store.match_route(&Method::Get, "/foo/John%20Doe.json");
     assert!(route_result.is_some());
 
     let route_result = route_result.unwrap();
-    assert_eq!(route_result.param("userid"), Some("John%20Doe"));
+    assert_eq!(route_result.param("userid"), Some("John Doe"));
     assert_eq!(route_result.param("format"), Some("json"));
 
     // ensure format works with queries
@@ -254,6 +438,77 @@ fn can_match_var_routes () {
     assert_eq!(route_result.param("format"), Some("markdown"));
 }
 
+#[test]
+fn decodes_params_after_matching() {
+    let route_store = &mut Router::new();
+
+    route_store.add_route(Method::Get, "/foo/:userid", middleware! { "hello from foo" });
+    route_store.add_route(Method::Get, "/files/*/:name", middleware! { "hello from files" });
+
+    // an encoded slash stays within its segment
+    let route_result = route_store.match_route(&Method::Get, "/foo/a%2Fb").unwrap();
+    assert_eq!(route_result.param("userid"), Some("a/b"));
+    assert_eq!(route_result.raw_param("userid"), Some("a%2Fb"));
+
+    let route_result = route_store.match_route(&Method::Get, "/files/a%2Fb/c%20d").unwrap();
+    assert_eq!(route_result.param("name"), Some("c d"));
+    assert!(route_store.match_route(&Method::Get, "/files/a/b/c").is_none());
+
+    // encoded unreserved characters are decoded before matching
+    let route_result = route_store.match_route(&Method::Get, "/%66oo/%4A%6Fhn").unwrap();
+    assert_eq!(route_result.raw_param("userid"), Some("John"));
+
+    // invalid escapes and UTF-8 sequences
+    let route_result = route_store.match_route(&Method::Get, "/foo/100%25%zz%C3%A9").unwrap();
+    assert_eq!(route_result.param("userid"), Some("100%%zz\u{e9}"));
+}
+
+#[test]
+fn matches_any_segment_character() {
+    let route_store = &mut Router::new();
+
+    route_store.add_route(Method::Get, "/users/:email", middleware! { "hello from users" });
+    route_store.add_route(Method::Get, "/releases/*", middleware! { "hello from releases" });
+
+    for &id in ["user@example.com", "v1.2.3", "~john", "a+b", "a:b", "caf\u{e9}", "(x)!$&'*;="].iter() {
+        let path = format!("/users/{}", id);
+        let route_result = route_store.match_route(&Method::Get, &path).unwrap();
+        assert_eq!(route_result.param("email"), Some(id));
+        assert_eq!(route_result.param("format"), Some(""));
+    }
+
+    // known extensions are still split off as the format
+    let route_result = route_store.match_route(&Method::Get, "/users/v1.2.3.json").unwrap();
+    assert_eq!(route_result.param("email"), Some("v1.2.3"));
+    assert_eq!(route_result.param("format"), Some("json"));
+
+    assert!(route_store.match_route(&Method::Get, "/releases/v1.2.3").is_some());
+    assert!(route_store.match_route(&Method::Get, "/releases/v1/2").is_none());
+
+    // unknown extensions stay part of the variable
+    let route_result = route_store.match_route(&Method::Get, "/users/notes.yaml").unwrap();
+    assert_eq!(route_result.param("email"), Some("notes.yaml"));
+    assert_eq!(route_result.param("format"), Some(""));
+}
+
+#[test]
+fn splits_explicit_formats_at_the_last_dot() {
+    let route_store = &mut Router::new();
+
+    route_store.add_route(Method::Get, "/foo/:userid.:format", middleware! { "hello from foo" });
+
+    let route_result = route_store.match_route(&Method::Get, "/foo/John.json").unwrap();
+    assert_eq!(route_result.param("userid"), Some("John"));
+    assert_eq!(route_result.param("format"), Some("json"));
+
+    let route_result = route_store.match_route(&Method::Get, "/foo/v1.2.3.yaml").unwrap();
+    assert_eq!(route_result.param("userid"), Some("v1.2.3"));
+    assert_eq!(route_result.param("format"), Some("yaml"));
+
+    // the dot in the route only matches a dot
+    assert!(route_store.match_route(&Method::Get, "/foo/John").is_none());
+}
+
 #[test]
 fn params_lifetime() {
     let route_store = &mut Router::new();
@@ -342,3 +597,73 @@ fn ignores_querystring() {
     let route_result = route_result.unwrap();
     assert_eq!(route_result.param("a"), Some("bar"));
 }
+
+#[test]
+fn runs_router_middleware_for_matching_routes() {
+    use Nickel;
+    use testing::{TestClient, TestRequest};
+    use hyper::header::Authorization;
+
+    let mut server = Nickel::new();
+
+    let mut admin = Router::new();
+    admin.utilize(middleware! { |req, res|
+        if !req.origin.headers.has::<Authorization<String>>() {
+            return res.error(StatusCode::Unauthorized, "Login required")
+        }
+    });
+    admin.get("/admin/:page", middleware! { |req|
+        format!("admin {}", req.param("page").unwrap())
+    });
+
+    let mut public = Router::new();
+    public.get("/", middleware!("public"));
+
+    server.utilize(admin);
+    server.utilize(public);
+
+    let client = TestClient::new(server);
+
+    assert_eq!(client.get("/").body_str(), Some("public"));
+    assert_eq!(client.get("/admin/users").status(), StatusCode::Unauthorized);
+
+    let req = TestRequest::new(Method::Get, "/admin/users")
+                  .header(Authorization("secret".to_string()));
+    assert_eq!(client.dispatch(req).body_str(), Some("admin users"));
+}
+
+#[test]
+fn runs_group_middleware_for_the_group_routes_only() {
+    use Nickel;
+    use testing::{TestClient, TestRequest};
+    use hyper::header::Authorization;
+
+    let mut router = Router::new();
+    router.utilize(middleware! { |_, mut res|
+        res.headers_mut().set_raw("X-Router", vec![b"yes".to_vec()]);
+    });
+    router.get("/", middleware!("public"));
+    router.group(|admin| {
+        admin.utilize(middleware! { |req, res|
+            if !req.origin.headers.has::<Authorization<String>>() {
+                return res.error(StatusCode::Unauthorized, "Login required")
+            }
+        });
+        admin.get("/admin", middleware!("admin"));
+    });
+    router.get("/about", middleware!("about"));
+
+    let mut server = Nickel::new();
+    server.utilize(router);
+    let client = TestClient::new(server);
+
+    assert_eq!(client.get("/").body_str(), Some("public"));
+    assert_eq!(client.get("/about").body_str(), Some("about"));
+    assert_eq!(client.get("/admin").status(), StatusCode::Unauthorized);
+
+    let req = TestRequest::new(Method::Get, "/admin")
+                  .header(Authorization("secret".to_string()));
+    let res = client.dispatch(req);
+    assert_eq!(res.body_str(), Some("admin"));
+    assert!(res.headers().get_raw("X-Router").is_some());
+}
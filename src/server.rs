@@ -1,42 +1,460 @@
-use std::net::ToSocketAddrs;
-use std::sync::{Arc, RwLock};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::any::Any;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use hyper::Result as HttpResult;
+use hyper::Error as HttpError;
 use hyper::server::{Request, Response, Handler, Listening};
 use hyper::server::Server as HyperServer;
+use hyper::net::{NetworkListener, HttpsListener, Openssl};
+use hyper::status::StatusCode;
+use hyper::header::{Connection, ConnectionOption, ContentLength};
 
 use middleware::MiddlewareStack;
+use proxy::TrustedProxy;
+use byte_range::RangeRequest;
+use closable_listener::{ClosableListener, Unbind};
+use tcp_listener::TcpSocketListener;
 use request;
 use response;
 
 pub struct Server {
     middleware_stack: MiddlewareStack,
-    templates: response::TemplateCache
+    templates: response::TemplateCache,
+    config: ServerConfig,
+    data: Box<ServerData>,
+    secret_key: Vec<u8>,
+    secure: bool,
+    activity: Mutex<Activity>,
+    idle: Condvar
+}
+
+/// Application state shared by all requests, see `Nickel::with_data`.
+pub trait ServerData: Send + Sync + 'static {
+    fn as_any(&self) -> &Any;
+}
+
+impl<T: Any + Send + Sync> ServerData for T {
+    fn as_any(&self) -> &Any {
+        self
+    }
+}
+
+// Number of requests currently being handled and whether the server
+// has been asked to stop taking new ones.
+struct Activity {
+    active: usize,
+    closing: bool
+}
+
+// Decrements the active request count when a request has been handled,
+// even if the handling thread unwinds.
+struct ActiveRequest<'a>(&'a Server);
+
+impl<'a> Drop for ActiveRequest<'a> {
+    fn drop(&mut self) {
+        let mut activity = self.0.activity.lock().unwrap();
+        activity.active -= 1;
+        if activity.active == 0 {
+            self.0.idle.notify_all();
+        }
+    }
 }
 
 // FIXME: Any better coherence solutions?
 struct ArcServer(Arc<Server>);
 
 impl Handler for ArcServer {
-    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, res: Response<'a>) {
-        let req: Request<'a, 'k> = req;
-        let nickel_req = request::Request::from_internal(req);
-        let nickel_res = response::Response::from_internal(res, &self.0.templates);
-        self.0.middleware_stack.invoke(nickel_req, nickel_res);
+    fn handle<'a, 'k>(&'a self, req: Request<'a, 'k>, mut res: Response<'a>) {
+        let _active = match self.0.begin_request() {
+            Some(active) => active,
+            None => {
+                debug!("Refusing {:?} {:?}, shutting down or too busy", req.method, req.uri);
+                *res.status_mut() = StatusCode::ServiceUnavailable;
+                res.headers_mut().set(Connection(vec![ConnectionOption::Close]));
+                res.headers_mut().set(ContentLength(0));
+                let _ = res.start().and_then(|res| res.end());
+                return
+            }
+        };
+
+        self.0.dispatch(req, res);
     }
 }
 
 impl Server {
-    pub fn new(middleware_stack: MiddlewareStack) -> Server {
+    pub fn new(middleware_stack: MiddlewareStack,
+               config: ServerConfig,
+               data: Box<ServerData>,
+               secret_key: Vec<u8>) -> Server {
         Server {
             middleware_stack: middleware_stack,
-            templates: RwLock::new(HashMap::new())
+            templates: RwLock::new(HashMap::new()),
+            config: config,
+            data: data,
+            secret_key: secret_key,
+            secure: false,
+            activity: Mutex::new(Activity { active: 0, closing: false }),
+            idle: Condvar::new()
+        }
+    }
+
+    pub fn serve<T: ToSocketAddrs>(self, addr: T) -> HttpResult<ListeningServer> {
+        let listener = try!(TcpSocketListener::bind(addr));
+        self.run(listener)
+    }
+
+    pub fn serve_https<T: ToSocketAddrs>(mut self, addr: T, cert: &Path, key: &Path)
+            -> HttpResult<ListeningServer> {
+        let ssl = try!(Openssl::with_cert_and_key(cert, key).map_err(|e| HttpError::Ssl(Box::new(e))));
+        let listener = try!(HttpsListener::new(addr, ssl));
+        self.secure = true;
+        self.run(listener)
+    }
+
+    pub fn serve_on<L>(self, listener: L) -> HttpResult<ListeningServer>
+            where L: NetworkListener + Send + 'static {
+        self.run(listener)
+    }
+
+    /// Whether the server only accepts connections over TLS.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// The application state passed to `Nickel::with_data`.
+    pub fn data(&self) -> &Any {
+        // `self.data.as_any()` would pick the blanket impl for the `Box`
+        // itself and hand out the box instead of the data in it
+        (*self.data).as_any()
+    }
+
+    /// The key used to sign and encrypt cookies.
+    pub fn secret_key(&self) -> &[u8] {
+        &self.secret_key
+    }
+
+    pub fn max_body_size(&self) -> u64 {
+        self.config.max_body_size
+    }
+
+    pub fn trusted_proxies(&self) -> &[TrustedProxy] {
+        &self.config.trusted_proxies
+    }
+
+    pub fn templates(&self) -> &response::TemplateCache {
+        &self.templates
+    }
+
+    fn run<L>(self, listener: L) -> HttpResult<ListeningServer>
+            where L: NetworkListener + Send + 'static {
+        let listener = ClosableListener::new(listener);
+        let unbind = listener.unbinder();
+
+        let mut hyper_server = HyperServer::new(listener);
+        match self.config.keep_alive {
+            Some(timeout) => hyper_server.keep_alive(timeout),
+            None => hyper_server.no_keep_alive()
         }
+        set_timeouts(&mut hyper_server, &self.config);
+
+        let threads = self.config.threads;
+        let server = Arc::new(self);
+        let handler = ArcServer(server.clone());
+        let listening = try!(match threads {
+            Some(threads) => hyper_server.handle_threads(handler, threads),
+            None => hyper_server.handle(handler)
+        });
+
+        Ok(ListeningServer {
+            listening: listening,
+            unbind: unbind,
+            server: server
+        })
     }
 
-    pub fn serve<T: ToSocketAddrs>(self, addr: T) -> HttpResult<Listening> {
-        let arc = ArcServer(Arc::new(self));
-        let server = try!(HyperServer::http(addr));
-        server.handle(arc)
+    /// Runs a request through the middleware stack.
+    pub fn dispatch<'a, 'k>(&'a self, req: Request<'a, 'k>, res: Response<'a>) {
+        let range = RangeRequest::from_request(&req);
+        let nickel_req = request::Request::from_internal(req, self);
+        let mut nickel_res = response::Response::from_internal(res, self);
+        response::set_range(&mut nickel_res, range);
+        self.middleware_stack.invoke(nickel_req, nickel_res);
     }
+
+    fn begin_request(&self) -> Option<ActiveRequest> {
+        let mut activity = self.activity.lock().unwrap();
+        if activity.closing {
+            return None
+        }
+
+        if let Some(max) = self.config.max_concurrent_requests {
+            if activity.active >= max {
+                return None
+            }
+        }
+
+        activity.active += 1;
+        Some(ActiveRequest(self))
+    }
+
+    fn close(&self) {
+        self.activity.lock().unwrap().closing = true;
+    }
+
+    // Stops taking new requests and waits for the active ones to finish.
+    // Returns `false` if requests were still running when `timeout` passed.
+    fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut activity = self.activity.lock().unwrap();
+        activity.closing = true;
+
+        while activity.active > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                warn!("Shutdown timed out with {} active requests", activity.active);
+                return false
+            }
+
+            activity = self.idle.wait_timeout(activity, deadline - now).unwrap().0;
+        }
+
+        true
+    }
+}
+
+// hyper only supports timeouts with its `timeouts` feature, which needs a
+// nightly compiler and is enabled by nickel's `unstable` feature.
+#[cfg(feature = "unstable")]
+fn set_timeouts<L: NetworkListener>(hyper_server: &mut HyperServer<L>, config: &ServerConfig) {
+    hyper_server.set_read_timeout(config.read_timeout);
+    hyper_server.set_write_timeout(config.write_timeout);
+}
+
+#[cfg(not(feature = "unstable"))]
+fn set_timeouts<L: NetworkListener>(_: &mut HyperServer<L>, config: &ServerConfig) {
+    if config.read_timeout.is_some() || config.write_timeout.is_some() {
+        warn!("Ignoring the read and write timeouts, they need nickel's `unstable` feature");
+    }
+}
+
+/// Settings for the underlying HTTP server.
+///
+/// # Examples
+/// ```{rust}
+/// use std::time::Duration;
+/// use nickel::{Nickel, ServerConfig};
+///
+/// let mut server = Nickel::new();
+/// server.configure(ServerConfig::new()
+///                      .threads(8)
+///                      .read_timeout(Duration::from_secs(10))
+///                      .write_timeout(Duration::from_secs(10))
+///                      .max_concurrent_requests(100));
+/// ```
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    threads: Option<usize>,
+    keep_alive: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    max_concurrent_requests: Option<usize>,
+    max_body_size: u64,
+    trusted_proxies: Vec<TrustedProxy>
+}
+
+impl ServerConfig {
+    /// Creates a config with a five second keep-alive, without timeouts and
+    /// without a limit on concurrent requests.
+    pub fn new() -> ServerConfig {
+        ServerConfig {
+            threads: None,
+            keep_alive: Some(Duration::from_secs(5)),
+            read_timeout: None,
+            write_timeout: None,
+            max_concurrent_requests: None,
+            max_body_size: 1024 * 1024,
+            trusted_proxies: vec![]
+        }
+    }
+
+    /// The number of worker threads handling connections.
+    ///
+    /// Defaults to hyper's choice, which depends on the number of CPUs.
+    pub fn threads(mut self, threads: usize) -> ServerConfig {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// How long idle connections are kept open for further requests.
+    ///
+    /// Pass `None` to close every connection after one request. Defaults to
+    /// five seconds.
+    pub fn keep_alive(mut self, timeout: Option<Duration>) -> ServerConfig {
+        self.keep_alive = timeout;
+        self
+    }
+
+    /// The time a connection may take to send the next part of a request.
+    ///
+    /// Only applied when nickel is built with the `unstable` feature, which
+    /// enables hyper's timeouts.
+    pub fn read_timeout(mut self, timeout: Duration) -> ServerConfig {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// The time a connection may take to accept the next part of a response.
+    ///
+    /// Only applied when nickel is built with the `unstable` feature.
+    pub fn write_timeout(mut self, timeout: Duration) -> ServerConfig {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// The maximum number of requests handled at the same time.
+    ///
+    /// Requests above the limit get a `503 Service Unavailable` response.
+    /// Only requests being handled count, idle keep-alive connections don't,
+    /// use `threads` and `keep_alive` to bound those.
+    pub fn max_concurrent_requests(mut self, max: usize) -> ServerConfig {
+        self.max_concurrent_requests = Some(max);
+        self
+    }
+
+    /// The largest request body, in bytes, that body parsers like
+    /// `FormBody` read into memory.
+    ///
+    /// Larger bodies get a `413 Payload Too Large` response. Defaults to
+    /// one megabyte.
+    pub fn max_body_size(mut self, max: u64) -> ServerConfig {
+        self.max_body_size = max;
+        self
+    }
+
+    /// Trusts the `Forwarded` and `X-Forwarded-*` headers sent by `proxy`,
+    /// which `Request::client_ip`, `scheme` and `host` use to find out
+    /// about the original request. Can be called several times.
+    ///
+    /// By default no proxy is trusted and the headers are ignored.
+    ///
+    /// # Examples
+    /// ```{rust}
+    /// use nickel::{Nickel, ServerConfig};
+    ///
+    /// let mut server = Nickel::new();
+    /// server.configure(ServerConfig::new()
+    ///                      .trust_proxy("127.0.0.1".parse().unwrap())
+    ///                      .trust_proxy("10.0.0.0/8".parse().unwrap()));
+    /// ```
+    pub fn trust_proxy(mut self, proxy: TrustedProxy) -> ServerConfig {
+        self.trusted_proxies.push(proxy);
+        self
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig::new()
+    }
+}
+
+/// A handle to a running server, as returned by `Nickel::start`.
+///
+/// Dropping the handle blocks the current thread for as long as the
+/// server is running, use `close` or `shutdown` to stop it instead.
+pub struct ListeningServer {
+    listening: Listening,
+    unbind: Box<Unbind>,
+    server: Arc<Server>
+}
+
+// How long closing the server waits for the acceptor threads to release
+// the socket.
+const UNBIND_TIMEOUT_SECS: u64 = 1;
+
+impl ListeningServer {
+    /// The address the server is bound to.
+    pub fn socket(&self) -> SocketAddr {
+        self.listening.socket
+    }
+
+    /// Blocks the current thread for as long as the server is running.
+    pub fn wait(self) {
+        // hyper joins the acceptor thread when `Listening` is dropped.
+        drop(self.listening)
+    }
+
+    /// Stops handling new requests without waiting for the requests which
+    /// are currently being handled.
+    ///
+    /// The socket is closed, so new connections are refused. Requests
+    /// arriving on connections which are already open get a
+    /// `503 Service Unavailable` response and their connection is closed.
+    pub fn close(mut self) {
+        self.server.close();
+        self.unbind.unbind(Duration::from_secs(UNBIND_TIMEOUT_SECS));
+        let _ = self.listening.close();
+    }
+
+    /// Closes the socket and waits up to `timeout` for the requests which
+    /// are currently being handled to finish.
+    ///
+    /// Returns `false` if some requests were still running when the timeout
+    /// expired.
+    ///
+    /// # Examples
+    /// ```{rust,no_run}
+    /// use std::time::Duration;
+    /// use nickel::Nickel;
+    ///
+    /// let server = Nickel::new().start("127.0.0.1:6767").unwrap();
+    /// // ...
+    /// if !server.shutdown(Duration::from_secs(30)) {
+    ///     println!("Some requests did not finish in time");
+    /// }
+    /// ```
+    pub fn shutdown(mut self, timeout: Duration) -> bool {
+        self.unbind.unbind(Duration::from_secs(UNBIND_TIMEOUT_SECS));
+        let _ = self.listening.close();
+        self.server.drain(timeout)
+    }
+}
+
+#[test]
+fn exposes_the_data_inside_the_box() {
+    let server = Server::new(MiddlewareStack::new(), ServerConfig::new(), Box::new(42u32), vec![]);
+    assert_eq!(server.data().downcast_ref::<u32>(), Some(&42));
+    assert!(server.data().downcast_ref::<Box<ServerData>>().is_none());
+}
+
+#[test]
+fn closes_connections_without_keep_alive() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use nickel::Nickel;
+    use router::HttpRouter;
+
+    let mut server = Nickel::new();
+    server.configure(ServerConfig::new()
+                         .threads(1)
+                         .keep_alive(None)
+                         .read_timeout(Duration::from_secs(5))
+                         .max_concurrent_requests(1));
+    server.get("/", middleware!("Hello"));
+    let server = server.start("127.0.0.1:0").unwrap();
+
+    let mut stream = TcpStream::connect(server.socket()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+    // without keep-alive the server hangs up after the response
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.ends_with("Hello"));
+
+    assert!(server.shutdown(Duration::from_secs(1)));
 }
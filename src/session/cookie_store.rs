@@ -0,0 +1,94 @@
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serialize::base64::{FromBase64, ToBase64, URL_SAFE};
+use serialize::json::{self, Json};
+
+use session::SessionStore;
+
+/// Keeps the whole session in the cookie sent to the client.
+///
+/// The cookie is signed, so clients can read the session data but can't
+/// change it. Browsers limit cookies to about 4KB, so this is only suited
+/// for small sessions.
+///
+/// The signed value includes when the session expires, so a cookie which
+/// was copied or kept by the client is only accepted until then.
+#[derive(Clone, Copy, Debug)]
+pub struct CookieStore {
+    ttl: Duration
+}
+
+impl CookieStore {
+    /// Creates a store whose sessions expire `ttl` after they were last
+    /// changed.
+    pub fn new(ttl: Duration) -> CookieStore {
+        CookieStore { ttl: ttl }
+    }
+}
+
+impl SessionStore for CookieStore {
+    fn load(&self, id: &str) -> Option<json::Object> {
+        let bytes = match id.from_base64() {
+            Ok(bytes) => bytes,
+            Err(_) => return None
+        };
+
+        let mut session = match String::from_utf8(bytes).ok().and_then(|s| Json::from_str(&s).ok()) {
+            Some(Json::Object(session)) => session,
+            _ => return None
+        };
+
+        match session.get("expires").and_then(|expires| expires.as_u64()) {
+            Some(expires) if expires > unix_time(SystemTime::now()) => {},
+            _ => return None
+        }
+
+        match session.remove("data") {
+            Some(Json::Object(data)) => Some(data),
+            _ => None
+        }
+    }
+
+    fn save(&self, _id: Option<&str>, data: &json::Object) -> io::Result<String> {
+        let expires = unix_time(SystemTime::now() + self.ttl);
+
+        let mut session = json::Object::new();
+        session.insert("data".to_string(), Json::Object(data.clone()));
+        session.insert("expires".to_string(), Json::U64(expires));
+
+        let encoded = Json::Object(session).to_string();
+        Ok(encoded.as_bytes().to_base64(URL_SAFE))
+    }
+
+    fn destroy(&self, _id: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
+
+#[test]
+fn keeps_the_data_in_the_id() {
+    let store = CookieStore::new(Duration::from_secs(60));
+    let mut data = json::Object::new();
+    data.insert("user".to_string(), Json::String("alice".to_string()));
+
+    let id = store.save(None, &data).unwrap();
+    assert!(id.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_'));
+    assert_eq!(store.load(&id), Some(data));
+    assert_eq!(store.load("not base64!"), None);
+}
+
+#[test]
+fn rejects_expired_sessions() {
+    let data = json::Object::new();
+
+    let id = CookieStore::new(Duration::from_secs(0)).save(None, &data).unwrap();
+    assert_eq!(CookieStore::new(Duration::from_secs(60)).load(&id), None);
+
+    // sessions without an expiry date are rejected too
+    let without_expiry = Json::Object(data).to_string().as_bytes().to_base64(URL_SAFE);
+    assert_eq!(CookieStore::new(Duration::from_secs(60)).load(&without_expiry), None);
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serialize::json;
+
+use session::{self, SessionStore};
+
+/// Keeps sessions in memory until they haven't been used for a while.
+///
+/// Sessions are lost when the server restarts and aren't shared between
+/// processes.
+pub struct MemoryStore {
+    sessions: Mutex<Sessions>,
+    ttl: Duration
+}
+
+struct Sessions {
+    entries: HashMap<String, Entry>,
+    // Expired sessions are removed when they're loaded, the ones which
+    // never come back are swept at most once per `ttl`.
+    next_sweep: Instant
+}
+
+struct Entry {
+    data: json::Object,
+    expires: Instant
+}
+
+impl MemoryStore {
+    /// Creates a store which drops sessions after `ttl` without requests.
+    pub fn new(ttl: Duration) -> MemoryStore {
+        MemoryStore {
+            sessions: Mutex::new(Sessions {
+                entries: HashMap::new(),
+                next_sweep: Instant::now() + ttl
+            }),
+            ttl: ttl
+        }
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Option<json::Object> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+
+        match sessions.entries.get_mut(id) {
+            Some(ref mut entry) if entry.expires > now => {
+                entry.expires = now + self.ttl;
+                return Some(entry.data.clone())
+            },
+            Some(_) => {},
+            None => return None
+        }
+
+        sessions.entries.remove(id);
+        None
+    }
+
+    fn save(&self, id: Option<&str>, data: &json::Object) -> io::Result<String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+
+        if now >= sessions.next_sweep {
+            sessions.entries.retain(|_, entry| entry.expires > now);
+            sessions.next_sweep = now + self.ttl;
+        }
+
+        let id = id.map(|id| id.to_string()).unwrap_or_else(session::random_id);
+        sessions.entries.insert(id.clone(), Entry {
+            data: data.clone(),
+            expires: now + self.ttl
+        });
+        Ok(id)
+    }
+
+    fn destroy(&self, id: &str) -> io::Result<()> {
+        self.sessions.lock().unwrap().entries.remove(id);
+        Ok(())
+    }
+}
+
+#[test]
+fn forgets_expired_sessions() {
+    let data = json::Object::new();
+
+    let store = MemoryStore::new(Duration::from_secs(60));
+    let id = store.save(None, &data).unwrap();
+    assert_eq!(store.load(&id), Some(data.clone()));
+    store.destroy(&id).unwrap();
+    assert_eq!(store.load(&id), None);
+
+    let store = MemoryStore::new(Duration::from_secs(0));
+    let id = store.save(None, &data).unwrap();
+    assert_eq!(store.load(&id), None);
+}
+
+#[test]
+fn sweeps_sessions_which_are_never_loaded() {
+    let data = json::Object::new();
+    let store = MemoryStore::new(Duration::from_secs(0));
+
+    store.save(None, &data).unwrap();
+    store.save(None, &data).unwrap();
+    assert_eq!(store.sessions.lock().unwrap().entries.len(), 1);
+}
@@ -0,0 +1,67 @@
+//! Sessions keep data for a client across requests. Add a
+//! `SessionMiddleware` in front of the handlers which use `req.session()`.
+//!
+//! # Examples
+//! ```{rust}
+//! #[macro_use] extern crate nickel;
+//!
+//! use std::time::Duration;
+//! use nickel::{Nickel, HttpRouter, Sessions, SessionMiddleware};
+//! use nickel::session::MemoryStore;
+//!
+//! fn main() {
+//!     let mut server = Nickel::new();
+//!     server.secret_key("a long, random string kept out of version control");
+//!     server.utilize(SessionMiddleware::new(MemoryStore::new(Duration::from_secs(30 * 60))));
+//!
+//!     server.post("/login/:user", middleware! { |req|
+//!         let session = req.session();
+//!         // prevent session fixation when the privileges change
+//!         session.regenerate();
+//!         session.set("user", &req.param("user").unwrap());
+//!         "Logged in"
+//!     });
+//!
+//!     server.get("/", middleware! { |req|
+//!         match req.session().get::<String>("user") {
+//!             Some(user) => format!("Hello {}", user),
+//!             None => "Hello stranger".to_string()
+//!         }
+//!     });
+//! }
+//! ```
+use std::io;
+use rand::{OsRng, Rng};
+use serialize::base64::{ToBase64, URL_SAFE};
+use serialize::json;
+
+pub use self::session::{Session, Sessions, SessionMiddleware};
+pub use self::cookie_store::CookieStore;
+pub use self::memory_store::MemoryStore;
+
+mod session;
+mod cookie_store;
+mod memory_store;
+
+/// Keeps the data of sessions between requests.
+///
+/// The ID of a session is sent to the client in a cookie signed with the key
+/// given to `Nickel::secret_key`.
+pub trait SessionStore: Send + Sync + 'static {
+    /// Retrieves the data of session `id`, or `None` if the session is
+    /// unknown or has expired.
+    fn load(&self, id: &str) -> Option<json::Object>;
+
+    /// Stores `data` under `id` or, for new sessions, a new ID. Returns the
+    /// ID to send to the client.
+    fn save(&self, id: Option<&str>, data: &json::Object) -> io::Result<String>;
+
+    /// Forgets session `id`.
+    fn destroy(&self, id: &str) -> io::Result<()>;
+}
+
+/// Creates a session ID which can't be guessed.
+pub fn random_id() -> String {
+    let mut rng = OsRng::new().ok().expect("Failed to access the OS random number generator");
+    rng.gen_iter::<u8>().take(24).collect::<Vec<u8>>().to_base64(URL_SAFE)
+}
@@ -0,0 +1,254 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use plugin::Extensible;
+use serialize::{Decodable, Encodable};
+use serialize::json::{self, Json};
+use typemap::Key;
+
+use cookies::{Cookies, CookieBuilder, SameSite};
+use middleware::{Middleware, MiddlewareResult, Continue};
+use request::Request;
+use response::Response;
+use session::SessionStore;
+
+/// The data kept for the client sending the current request.
+///
+/// Values are stored as JSON, so anything implementing `Encodable` and
+/// `Decodable` can be put into a session.
+pub struct Session {
+    state: RefCell<State>
+}
+
+struct State {
+    id: Option<String>,
+    data: json::Object,
+    modified: bool,
+    regenerate: bool
+}
+
+impl Session {
+    fn new(id: Option<String>, data: json::Object) -> Session {
+        Session {
+            state: RefCell::new(State {
+                id: id,
+                data: data,
+                modified: false,
+                regenerate: false
+            })
+        }
+    }
+
+    /// Retrieves the value stored under `key`, or `None` if there is no
+    /// value or it isn't a `T`.
+    pub fn get<T: Decodable>(&self, key: &str) -> Option<T> {
+        let state = self.state.borrow();
+        state.data.get(key).and_then(|value| {
+            T::decode(&mut json::Decoder::new(value.clone())).ok()
+        })
+    }
+
+    /// Stores `value` under `key`, replacing the previous value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` can't be represented as JSON, for example a map
+    /// whose keys aren't strings.
+    pub fn set<T: Encodable>(&self, key: &str, value: &T) {
+        let value = json::encode(value).ok()
+                                       .and_then(|encoded| Json::from_str(&encoded).ok())
+                                       .expect("Failed to encode session value");
+
+        let mut state = self.state.borrow_mut();
+        state.data.insert(key.to_string(), value);
+        state.modified = true;
+    }
+
+    /// Removes the value stored under `key`.
+    pub fn remove(&self, key: &str) {
+        let mut state = self.state.borrow_mut();
+        if state.data.remove(key).is_some() {
+            state.modified = true;
+        }
+    }
+
+    /// Removes all values. Empty sessions are deleted from the store and
+    /// their cookie is removed.
+    pub fn clear(&self) {
+        let mut state = self.state.borrow_mut();
+        state.data.clear();
+        state.modified = true;
+    }
+
+    /// Moves the session to a new ID when the response is sent, keeping
+    /// its values.
+    ///
+    /// Call this whenever the privileges of the client change, like when
+    /// logging in, so that an attacker who got hold of the previous ID
+    /// can't use it.
+    pub fn regenerate(&self) {
+        self.state.borrow_mut().regenerate = true;
+    }
+}
+
+struct SessionKey;
+impl Key for SessionKey { type Value = Rc<Session>; }
+
+/// Loads the session for each request from a `SessionStore` and saves it
+/// when the response is sent.
+///
+/// Sessions are saved right before the headers are sent, values set after
+/// a handler started streaming the body are lost.
+pub struct SessionMiddleware<S> {
+    store: S,
+    cookie_name: String,
+    secure: bool
+}
+
+impl<S: SessionStore> SessionMiddleware<S> {
+    /// Creates a middleware keeping sessions in `store`, under the
+    /// cookie `nickel.session`.
+    pub fn new(store: S) -> SessionMiddleware<S> {
+        SessionMiddleware {
+            store: store,
+            cookie_name: "nickel.session".to_string(),
+            secure: false
+        }
+    }
+
+    /// The name of the cookie holding the session ID.
+    pub fn cookie_name<N: Into<String>>(mut self, name: N) -> SessionMiddleware<S> {
+        self.cookie_name = name.into();
+        self
+    }
+
+    /// Only sends the session cookie over TLS connections.
+    pub fn secure(mut self, secure: bool) -> SessionMiddleware<S> {
+        self.secure = secure;
+        self
+    }
+
+    fn save(&self, session: &Session, res: &mut Response) {
+        let mut state = session.state.borrow_mut();
+        if !state.modified && !state.regenerate {
+            return
+        }
+
+        let existed = state.id.is_some();
+        if state.regenerate || state.data.is_empty() {
+            if let Some(id) = state.id.take() {
+                if let Err(e) = self.store.destroy(&id) {
+                    error!("Failed to destroy session: {}", e);
+                }
+            }
+        }
+
+        if state.data.is_empty() {
+            if existed {
+                res.remove_cookie(&self.cookie_name);
+            }
+            return
+        }
+
+        match self.store.save(state.id.as_ref().map(|id| &**id), &state.data) {
+            Ok(id) => {
+                res.set_signed_cookie(CookieBuilder::new(&*self.cookie_name, id)
+                                          .path("/")
+                                          .http_only(true)
+                                          .secure(self.secure)
+                                          .same_site(SameSite::Lax));
+            },
+            Err(e) => error!("Failed to save session: {}", e)
+        }
+    }
+}
+
+impl<S: SessionStore> Middleware for SessionMiddleware<S> {
+    fn invoke<'mw, 'conn>(&'mw self, req: &mut Request<'mw, 'conn>, mut res: Response<'mw>)
+                          -> MiddlewareResult<'mw> {
+        let loaded = req.cookies()
+                        .get_signed(&self.cookie_name)
+                        .and_then(|id| self.store.load(&id).map(|data| (id, data)));
+
+        let session = Rc::new(match loaded {
+            Some((id, data)) => Session::new(Some(id), data),
+            None => Session::new(None, json::Object::new())
+        });
+
+        req.extensions_mut().insert::<SessionKey>(session.clone());
+        res.on_send(move |res| self.save(&session, res));
+
+        Ok(Continue(res))
+    }
+}
+
+pub trait Sessions {
+    /// Retrieve the session of the current `Request`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the request didn't pass through a `SessionMiddleware`.
+    fn session(&self) -> &Session;
+}
+
+impl<'mw, 'conn> Sessions for Request<'mw, 'conn> {
+    fn session(&self) -> &Session {
+        self.extensions()
+            .get::<SessionKey>()
+            .map(|session| &**session)
+            .expect("No session, add a SessionMiddleware in front of this handler")
+    }
+}
+
+#[test]
+fn keeps_values_across_requests() {
+    use std::time::Duration;
+    use hyper::header::{Cookie, SetCookie};
+    use hyper::method::Method;
+    use nickel::Nickel;
+    use router::HttpRouter;
+    use session::MemoryStore;
+    use testing::{TestClient, TestRequest, TestResponse};
+
+    let mut server = Nickel::new();
+    server.utilize(SessionMiddleware::new(MemoryStore::new(Duration::from_secs(60))));
+    server.get("/visit", middleware! { |req|
+        let session = req.session();
+        let visits = session.get::<u32>("visits").unwrap_or(0) + 1;
+        session.set("visits", &visits);
+        visits.to_string()
+    });
+    server.get("/login", middleware! { |req|
+        req.session().regenerate();
+        "ok"
+    });
+    server.get("/logout", middleware! { |req|
+        req.session().clear();
+        "bye"
+    });
+
+    let client = TestClient::new(server);
+    let get = |path: &str, cookies: &SetCookie| {
+        let req = TestRequest::new(Method::Get, path).header(Cookie(cookies.0.clone()));
+        client.dispatch(req)
+    };
+    let set_cookie = |res: &TestResponse| {
+        res.headers().get::<SetCookie>().unwrap().clone()
+    };
+
+    let first = client.get("/visit");
+    assert_eq!(first.body_str(), Some("1"));
+    let cookies = set_cookie(&first);
+
+    let second = get("/visit", &cookies);
+    assert_eq!(second.body_str(), Some("2"));
+
+    // the old ID stops working once the session has been regenerated
+    let regenerated = set_cookie(&get("/login", &cookies));
+    assert!(regenerated.0[0].value != cookies.0[0].value);
+    assert_eq!(get("/visit", &regenerated).body_str(), Some("3"));
+    assert_eq!(get("/visit", &cookies).body_str(), Some("1"));
+
+    let removed = set_cookie(&get("/logout", &regenerated));
+    assert_eq!(removed.0[0].value, "");
+    assert_eq!(get("/visit", &regenerated).body_str(), Some("1"));
+}
@@ -0,0 +1,51 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+
+use hyper;
+use hyper::net::{NetworkListener, HttpStream};
+
+/// A `NetworkListener` accepting plain HTTP connections on a TCP socket,
+/// for serving a `TcpListener` that was bound elsewhere.
+///
+/// # Examples
+/// ```{rust,no_run}
+/// use std::net::TcpListener;
+/// use nickel::{Nickel, TcpSocketListener};
+///
+/// let listener = TcpListener::bind("127.0.0.1:6767").unwrap();
+/// let server = Nickel::new().start_on(TcpSocketListener::from(listener)).unwrap();
+/// # server.close();
+/// ```
+pub struct TcpSocketListener(TcpListener);
+
+impl TcpSocketListener {
+    /// Creates a new socket bound to the given address.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpSocketListener> {
+        TcpListener::bind(addr).map(TcpSocketListener)
+    }
+}
+
+impl From<TcpListener> for TcpSocketListener {
+    fn from(listener: TcpListener) -> TcpSocketListener {
+        TcpSocketListener(listener)
+    }
+}
+
+impl Clone for TcpSocketListener {
+    fn clone(&self) -> TcpSocketListener {
+        TcpSocketListener(self.0.try_clone().unwrap())
+    }
+}
+
+impl NetworkListener for TcpSocketListener {
+    type Stream = HttpStream;
+
+    fn accept(&mut self) -> hyper::Result<HttpStream> {
+        let (stream, _) = try!(self.0.accept());
+        Ok(HttpStream(stream))
+    }
+
+    fn local_addr(&mut self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+}
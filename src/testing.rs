@@ -0,0 +1,318 @@
+//! Run requests through a `Nickel` application without binding a port.
+//!
+//! # Examples
+//! ```{rust}
+//! #[macro_use] extern crate nickel;
+//!
+//! use nickel::{Nickel, HttpRouter};
+//! use nickel::status::StatusCode;
+//! use nickel::testing::TestClient;
+//!
+//! fn main() {
+//!     let mut server = Nickel::new();
+//!     server.get("/hello", middleware!("Hello World"));
+//!
+//!     let client = TestClient::new(server);
+//!
+//!     let res = client.get("/hello");
+//!     assert_eq!(res.status(), StatusCode::Ok);
+//!     assert_eq!(res.body_str(), Some("Hello World"));
+//!
+//!     assert_eq!(client.get("/missing").status(), StatusCode::NotFound);
+//! }
+//! ```
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read, Write};
+use std::net::{SocketAddr, SocketAddrV4, Ipv4Addr};
+use std::str;
+
+use hyper::buffer::BufReader;
+use hyper::header::{Headers, Header, HeaderFormat, ContentLength, TransferEncoding, Encoding};
+use hyper::method::Method;
+use hyper::net::NetworkStream;
+use hyper::server::Request as HyperRequest;
+use hyper::server::Response as HyperResponse;
+use hyper::status::StatusCode;
+
+use nickel::{self, Nickel};
+use server::Server;
+
+/// Dispatches `TestRequest`s through the middleware stack and error
+/// handlers of a `Nickel` application.
+pub struct TestClient {
+    server: Server
+}
+
+impl TestClient {
+    /// Wraps the application, exactly as `listen` would serve it.
+    pub fn new(app: Nickel) -> TestClient {
+        TestClient { server: nickel::into_server(app) }
+    }
+
+    /// Sends a `GET` request for `path`.
+    pub fn get(&self, path: &str) -> TestResponse {
+        self.dispatch(TestRequest::new(Method::Get, path))
+    }
+
+    /// Sends a `POST` request for `path` with the given body.
+    pub fn post<B: Into<Vec<u8>>>(&self, path: &str, body: B) -> TestResponse {
+        self.dispatch(TestRequest::new(Method::Post, path).body(body))
+    }
+
+    /// Sends a request and collects the response.
+    ///
+    /// # Panics
+    ///
+    /// Panics if hyper can't parse the request, for example when the path
+    /// contains spaces.
+    pub fn dispatch(&self, req: TestRequest) -> TestResponse {
+        let mut stream = MockStream {
+            input: Cursor::new(req.to_bytes()),
+            addr: req.remote_addr
+        };
+        let mut output = vec![];
+        let mut headers = Headers::new();
+
+        {
+            let mut reader = BufReader::new(&mut stream as &mut NetworkStream);
+            let hyper_req = HyperRequest::new(&mut reader, req.remote_addr)
+                                         .ok()
+                                         .expect("Invalid test request");
+            let hyper_res = HyperResponse::new(&mut output, &mut headers);
+
+            self.server.dispatch(hyper_req, hyper_res);
+        }
+
+        TestResponse::parse(&output).ok().expect("Invalid response")
+    }
+}
+
+/// A request to be sent by a `TestClient`.
+pub struct TestRequest {
+    method: Method,
+    path: String,
+    headers: Headers,
+    body: Vec<u8>,
+    remote_addr: SocketAddr
+}
+
+impl TestRequest {
+    /// Creates a request without headers or body, coming from `127.0.0.1`.
+    pub fn new(method: Method, path: &str) -> TestRequest {
+        TestRequest {
+            method: method,
+            path: path.to_string(),
+            headers: Headers::new(),
+            body: vec![],
+            remote_addr: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 40000))
+        }
+    }
+
+    /// Sets a header on the request.
+    pub fn header<H: Header + HeaderFormat>(mut self, header: H) -> TestRequest {
+        self.headers.set(header);
+        self
+    }
+
+    /// Sets a header from its name and raw value, for headers hyper has
+    /// no type for.
+    pub fn raw_header(mut self, name: &str, value: &str) -> TestRequest {
+        self.headers.set_raw(name.to_string(), vec![value.as_bytes().to_vec()]);
+        self
+    }
+
+    /// Sets the request body, along with a matching `Content-Length`.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> TestRequest {
+        self.body = body.into();
+        self
+    }
+
+    /// Sets the address the request claims to come from.
+    pub fn remote_addr(mut self, addr: SocketAddr) -> TestRequest {
+        self.remote_addr = addr;
+        self
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut headers = self.headers.clone();
+        if !self.body.is_empty() {
+            headers.set(ContentLength(self.body.len() as u64));
+        }
+
+        let mut bytes = format!("{} {} HTTP/1.1\r\n{}\r\n", self.method, self.path, headers)
+                            .into_bytes();
+        bytes.extend(self.body.iter().cloned());
+        bytes
+    }
+}
+
+/// The status, headers and body a `TestClient` received.
+pub struct TestResponse {
+    status: StatusCode,
+    headers: Headers,
+    body: Vec<u8>
+}
+
+impl TestResponse {
+    /// The status of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The headers of the response.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// The body of the response, with any chunked encoding removed.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The body of the response, or `None` if it isn't valid UTF-8.
+    pub fn body_str(&self) -> Option<&str> {
+        str::from_utf8(&self.body).ok()
+    }
+
+    fn parse(raw: &[u8]) -> Result<TestResponse, &'static str> {
+        let split = try!(raw.windows(4)
+                            .position(|w| w == b"\r\n\r\n")
+                            .ok_or("Missing end of headers"));
+        let head = try!(str::from_utf8(&raw[..split]).map_err(|_| "Invalid headers"));
+        let rest = &raw[split + 4..];
+
+        let mut lines = head.split("\r\n");
+        let status = try!(lines.next()
+                               .and_then(|line| line.split(' ').nth(1))
+                               .and_then(|code| code.parse().ok())
+                               .ok_or("Invalid status line"));
+
+        let mut raw_headers: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+        for line in lines {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next().unwrap();
+            let value = try!(parts.next().ok_or("Invalid header line"));
+            raw_headers.entry(name.to_string())
+                       .or_insert(vec![])
+                       .push(value.trim().as_bytes().to_vec());
+        }
+
+        let mut headers = Headers::new();
+        for (name, values) in raw_headers {
+            headers.set_raw(name, values);
+        }
+
+        let chunked = headers.get::<TransferEncoding>()
+                             .map_or(false, |te| te.contains(&Encoding::Chunked));
+        let body = if chunked {
+            try!(dechunk(rest))
+        } else {
+            match headers.get::<ContentLength>() {
+                Some(&ContentLength(len)) if (len as usize) < rest.len() => {
+                    rest[..len as usize].to_vec()
+                }
+                _ => rest.to_vec()
+            }
+        };
+
+        Ok(TestResponse {
+            status: StatusCode::from_u16(status),
+            headers: headers,
+            body: body
+        })
+    }
+}
+
+fn dechunk(mut raw: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut body = vec![];
+
+    loop {
+        let line_end = try!(raw.windows(2)
+                               .position(|w| w == b"\r\n")
+                               .ok_or("Missing chunk size"));
+        let size = try!(str::from_utf8(&raw[..line_end]).ok()
+                            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+                            .ok_or("Invalid chunk size"));
+        raw = &raw[line_end + 2..];
+
+        if size == 0 {
+            return Ok(body)
+        }
+
+        if raw.len() < size + 2 {
+            return Err("Truncated chunk")
+        }
+
+        body.extend(raw[..size].iter().cloned());
+        raw = &raw[size + 2..];
+    }
+}
+
+// Feeds a serialized request to hyper's parser.
+struct MockStream {
+    input: Cursor<Vec<u8>>,
+    addr: SocketAddr
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NetworkStream for MockStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+#[test]
+fn dispatches_through_routes_and_error_handlers() {
+    use router::HttpRouter;
+
+    let mut server = Nickel::new();
+    server.get("/hello/:name", middleware! { |req|
+        format!("Hello {}", req.param("name").unwrap())
+    });
+
+    let client = TestClient::new(server);
+
+    let res = client.get("/hello/nickel");
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(res.body_str(), Some("Hello nickel"));
+
+    let res = client.get("/nowhere");
+    assert_eq!(res.status(), StatusCode::NotFound);
+    assert_eq!(res.body_str(), Some("Not Found"));
+}
+
+#[test]
+fn sends_headers_and_body() {
+    use hyper::header::UserAgent;
+    use router::HttpRouter;
+
+    let mut server = Nickel::new();
+    server.post("/echo", middleware! { |req|
+        let mut body = String::new();
+        req.origin.read_to_string(&mut body).unwrap();
+        let agent = req.origin.headers.get::<UserAgent>().unwrap().0.clone();
+        format!("{}: {}", agent, body)
+    });
+
+    let client = TestClient::new(server);
+    let req = TestRequest::new(Method::Post, "/echo")
+                  .header(UserAgent("tests".to_string()))
+                  .body("ping");
+
+    assert_eq!(client.dispatch(req).body_str(), Some("tests: ping"));
+}
@@ -0,0 +1,147 @@
+use std::env;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{SocketAddr, SocketAddrV4, Ipv4Addr, Shutdown};
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+use hyper;
+use hyper::net::{NetworkListener, NetworkStream};
+use unix_socket::{UnixListener, UnixStream};
+use libc;
+
+/// The first file descriptor passed by systemd socket activation.
+pub const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// A `NetworkListener` accepting connections on a Unix domain socket.
+///
+/// Connections on Unix sockets don't have an IP address, so requests
+/// arriving through this listener report `0.0.0.0:0` as their
+/// `remote_addr`.
+///
+/// # Examples
+/// ```{rust,no_run}
+/// use nickel::{Nickel, UnixSocketListener};
+///
+/// let listener = UnixSocketListener::bind("/tmp/nickel.sock").unwrap();
+/// let server = Nickel::new().start_on(listener).unwrap();
+/// # server.close();
+/// ```
+pub struct UnixSocketListener(UnixListener);
+
+impl UnixSocketListener {
+    /// Creates a new socket bound to the given path.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixSocketListener> {
+        UnixListener::bind(path).map(UnixSocketListener)
+    }
+}
+
+impl From<UnixListener> for UnixSocketListener {
+    fn from(listener: UnixListener) -> UnixSocketListener {
+        UnixSocketListener(listener)
+    }
+}
+
+impl Clone for UnixSocketListener {
+    fn clone(&self) -> UnixSocketListener {
+        UnixSocketListener(self.0.try_clone().unwrap())
+    }
+}
+
+impl NetworkListener for UnixSocketListener {
+    type Stream = UnixSocketStream;
+
+    fn accept(&mut self) -> hyper::Result<UnixSocketStream> {
+        let stream = try!(self.0.accept());
+        Ok(UnixSocketStream(stream))
+    }
+
+    fn local_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(unspecified_addr())
+    }
+}
+
+/// A connection accepted by a `UnixSocketListener`.
+pub struct UnixSocketStream(UnixStream);
+
+impl Clone for UnixSocketStream {
+    fn clone(&self) -> UnixSocketStream {
+        UnixSocketStream(self.0.try_clone().unwrap())
+    }
+}
+
+impl Read for UnixSocketStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for UnixSocketStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl NetworkStream for UnixSocketStream {
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        Ok(unspecified_addr())
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        self.0.shutdown(how)
+    }
+}
+
+// The address family, e.g. `AF_INET` or `AF_UNIX`, of the socket `fd`.
+pub fn socket_family(fd: RawFd) -> io::Result<libc::c_int> {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len)
+    };
+
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(addr.ss_family as libc::c_int)
+    }
+}
+
+// The number of sockets systemd passed to this process, starting at
+// `SD_LISTEN_FDS_START`. `LISTEN_PID` tells whether they were meant for
+// this process or for a parent which didn't unset the variables.
+pub fn systemd_listen_fds() -> usize {
+    let pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<libc::pid_t>().ok());
+    if pid != Some(unsafe { libc::getpid() }) {
+        return 0
+    }
+
+    env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse().ok()).unwrap_or(0)
+}
+
+fn unspecified_addr() -> SocketAddr {
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0))
+}
+
+#[test]
+fn tells_socket_families_apart() {
+    use std::fs;
+    use std::net::TcpListener;
+    use std::os::unix::io::AsRawFd;
+
+    let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+    assert_eq!(socket_family(tcp.as_raw_fd()).unwrap(), libc::AF_INET);
+
+    let path = env::temp_dir().join("nickel-socket-family-test.sock");
+    let _ = fs::remove_file(&path);
+    let unix = UnixListener::bind(&path).unwrap();
+    assert_eq!(socket_family(unix.as_raw_fd()).unwrap(), libc::AF_UNIX);
+    fs::remove_file(&path).unwrap();
+
+    let file = fs::File::open("Cargo.toml").unwrap();
+    assert!(socket_family(file.as_raw_fd()).is_err());
+}
@@ -3,7 +3,11 @@ use url::form_urlencoded;
 use groupable::Groupable;
 
 pub fn parse (encoded_string : &str) -> HashMap<String, Vec<String>> {
-    form_urlencoded::parse(encoded_string.as_bytes()).into_iter().group()
+    parse_bytes(encoded_string.as_bytes())
+}
+
+pub fn parse_bytes(encoded: &[u8]) -> HashMap<String, Vec<String>> {
+    form_urlencoded::parse(encoded).into_iter().group()
 }
 
 #[test]